@@ -1,58 +1,188 @@
 use regex::Regex;
-use std::collections::HashMap;
+use std::env;
 use std::io::{stdin, Read};
 
-fn solve_part_1(items: &[&str]) -> u32 {
-    let rx = Regex::new(r"^(\d+)-(\d+) ([a-z]): ([a-z]+)$").unwrap();
-    let mut counter: u32 = 0;
+#[derive(Debug)]
+struct Entry {
+    first: usize,
+    second: usize,
+    letter: char,
+    password: Vec<char>,
+}
+
+// A repaired password and the number of substituted characters
+#[derive(Debug)]
+struct Repair {
+    password: String,
+    substitutions: usize,
+}
 
-    for item in items.iter() {
-        if let Some(cap) = rx.captures(item) {
-            let min: u8 = cap[1].parse().unwrap();
-            let max: u8 = cap[2].parse().unwrap();
-            let letter: char = cap[3].chars().next().unwrap();
-            let password: Vec<char> = cap[4].chars().collect();
+impl Entry {
+    fn parse_all(items: &[&str]) -> Vec<Entry> {
+        let rx = Regex::new(r"^(\d+)-(\d+) ([a-z]): ([a-z]+)$").unwrap();
+        let mut entries = Vec::new();
 
-            let mut hm = HashMap::new();
-            for ch in password.iter() {
-                *hm.entry(ch).or_insert(0) += 1;
+        for item in items.iter() {
+            if let Some(cap) = rx.captures(item) {
+                entries.push(Entry {
+                    first: cap[1].parse().unwrap(),
+                    second: cap[2].parse().unwrap(),
+                    letter: cap[3].chars().next().unwrap(),
+                    password: cap[4].chars().collect(),
+                });
+            } else {
+                println!("Invalid input: {}", item);
             }
+        }
+
+        entries
+    }
+
+    fn letter_count(&self) -> usize {
+        self.password
+            .iter()
+            .filter(|&&ch| ch == self.letter)
+            .count()
+    }
+
+    fn is_valid_part_1(&self) -> bool {
+        let amount = self.letter_count();
+
+        amount > 0 && self.first <= amount && amount <= self.second
+    }
 
-            if let Some(&amount) = hm.get(&letter) {
-                if min <= amount && amount <= max {
-                    counter += 1;
+    fn position_matches(&self, position: usize) -> bool {
+        position >= 1 && self.password.get(position - 1) == Some(&self.letter)
+    }
+
+    fn is_valid_part_2(&self) -> bool {
+        self.position_matches(self.first) ^ self.position_matches(self.second)
+    }
+
+    // Any letter other than the policy letter will do as a replacement
+    fn other_letter(&self) -> char {
+        ('a'..='z').find(|&ch| ch != self.letter).unwrap()
+    }
+
+    fn repair_part_1(&self) -> Option<Repair> {
+        let amount = self.letter_count();
+        let target = self.first.max(1);
+        if target > self.second || target > self.password.len() {
+            return None;
+        }
+
+        let mut password = self.password.clone();
+        let mut substitutions = 0;
+
+        if amount < target {
+            for ch in password.iter_mut().filter(|ch| **ch != self.letter) {
+                if amount + substitutions == target {
+                    break;
                 }
+                *ch = self.letter;
+                substitutions += 1;
+            }
+        } else if amount > self.second {
+            let other = self.other_letter();
+            for ch in password.iter_mut().rev().filter(|ch| **ch == self.letter) {
+                if amount - substitutions == self.second {
+                    break;
+                }
+                *ch = other;
+                substitutions += 1;
             }
-        } else {
-            println!("Invalid input: {}", item);
         }
+
+        Some(Repair {
+            password: password.into_iter().collect(),
+            substitutions,
+        })
     }
 
-    counter
-}
+    fn repair_part_2(&self) -> Option<Repair> {
+        let in_range = |position: usize| position >= 1 && position <= self.password.len();
+
+        // Both checks read the same character, so exactly one can never match
+        if self.first == self.second {
+            return None;
+        }
+
+        let mut password = self.password.clone();
+        let mut substitutions = 0;
 
-fn solve_part_2(items: &[&str]) -> u32 {
-    let rx = Regex::new(r"^(\d+)-(\d+) ([a-z]): ([a-z]+)$").unwrap();
-    let mut counter: u32 = 0;
-
-    for item in items.iter() {
-        if let Some(cap) = rx.captures(item) {
-            let position_1: usize = cap[1].parse::<usize>().unwrap() - 1;
-            let position_2: usize = cap[2].parse::<usize>().unwrap() - 1;
-            let letter: char = cap[3].chars().next().unwrap();
-            let password: Vec<char> = cap[4].chars().collect();
-
-            let state_1 = letter == password[position_1];
-            let state_2 = letter == password[position_2];
-            if state_1 ^ state_2 {
-                counter += 1;
+        match (
+            self.position_matches(self.first),
+            self.position_matches(self.second),
+        ) {
+            (true, true) => {
+                password[self.second - 1] = self.other_letter();
+                substitutions += 1;
             }
-        } else {
-            println!("Invalid input: {}", item);
+            (false, false) => {
+                let position = if in_range(self.first) {
+                    self.first
+                } else if in_range(self.second) {
+                    self.second
+                } else {
+                    return None;
+                };
+                password[position - 1] = self.letter;
+                substitutions += 1;
+            }
+            _ => {}
         }
+
+        Some(Repair {
+            password: password.into_iter().collect(),
+            substitutions,
+        })
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{}-{} {}: {}",
+            self.first,
+            self.second,
+            self.letter,
+            self.password.iter().collect::<String>()
+        )
     }
+}
+
+fn solve_part_1(entries: &[Entry]) -> usize {
+    entries.iter().filter(|e| e.is_valid_part_1()).count()
+}
+
+fn solve_part_2(entries: &[Entry]) -> usize {
+    entries.iter().filter(|e| e.is_valid_part_2()).count()
+}
+
+fn print_repair(part: u8, repair: Option<Repair>) {
+    match repair {
+        Some(repair) => println!(
+            "  part {}: suggest {} ({} substitutions)",
+            part, repair.password, repair.substitutions
+        ),
+        None => println!("  part {}: no repair by substitution is possible", part),
+    }
+}
+
+fn print_repairs(entries: &[Entry]) {
+    for entry in entries.iter() {
+        let valid_part_1 = entry.is_valid_part_1();
+        let valid_part_2 = entry.is_valid_part_2();
+        if valid_part_1 && valid_part_2 {
+            continue;
+        }
 
-    counter
+        println!("{}", entry.describe());
+        if !valid_part_1 {
+            print_repair(1, entry.repair_part_1());
+        }
+        if !valid_part_2 {
+            print_repair(2, entry.repair_part_2());
+        }
+    }
 }
 
 fn main() {
@@ -60,7 +190,36 @@ fn main() {
     stdin().read_to_string(&mut input).unwrap();
 
     let items: Vec<&str> = input.lines().collect();
+    let entries = Entry::parse_all(&items);
 
-    println!("Part 1: {} passwords are valid", solve_part_1(&items));
-    println!("Part 2: {} passwords are valid", solve_part_2(&items));
+    println!("Part 1: {} passwords are valid", solve_part_1(&entries));
+    println!("Part 2: {} passwords are valid", solve_part_2(&entries));
+
+    if env::args().any(|arg| arg == "--repair") {
+        print_repairs(&entries);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn repair_part_1() {
+        use crate::Entry;
+
+        let entries = Entry::parse_all(&["1-3 b: cdefg", "2-3 d: ddddx"]);
+        assert_eq!(entries[0].repair_part_1().unwrap().password, "bdefg");
+        let repair = entries[1].repair_part_1().unwrap();
+        assert_eq!(repair.password, "dddax");
+        assert_eq!(repair.substitutions, 1);
+    }
+
+    #[test]
+    fn repair_part_2() {
+        use crate::Entry;
+
+        let entries = Entry::parse_all(&["2-9 c: ccccccccc", "1-9 c: abcde", "3-3 a: bcd"]);
+        assert_eq!(entries[0].repair_part_2().unwrap().password, "cccccccca");
+        assert_eq!(entries[1].repair_part_2().unwrap().password, "cbcde");
+        assert!(entries[2].repair_part_2().is_none());
+    }
 }
@@ -1,48 +1,437 @@
-use std::io::{stdin, Read};
+use std::env;
+use std::fs;
+use std::io::{stdin, stdout, IsTerminal, Read};
 
-#[derive(Debug, PartialEq)]
-enum Square {
-    Tree,
-    Open,
+#[derive(Debug)]
+struct Terrain {
+    symbol: char,
+    name: String,
+    cost: u32,
 }
 
-fn calculate_encounters(area: &[Vec<Square>], x_offset: usize, y_offset: usize) -> u32 {
-    let mut x = 0;
+#[derive(Debug)]
+struct Legend {
+    terrains: Vec<Terrain>,
+}
+
+// A square holds the index of its terrain in the legend
+#[derive(Debug)]
+struct Square(usize);
+
+#[derive(Debug)]
+struct Encounters {
+    cost: u64,
+    counts: Vec<u32>,
+}
+
+impl Legend {
+    // The puzzle's own terrain: trees cost one encounter, open squares nothing
+    fn new() -> Legend {
+        let terrains = vec![
+            Terrain {
+                symbol: '.',
+                name: "open".to_string(),
+                cost: 0,
+            },
+            Terrain {
+                symbol: '#',
+                name: "tree".to_string(),
+                cost: 1,
+            },
+        ];
+
+        Legend { terrains }
+    }
+
+    // One terrain per line: symbol, name and cost, e.g. "~ ice 2"
+    fn parse(text: &str) -> Result<Legend, String> {
+        let mut terrains: Vec<Terrain> = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            if fields.len() != 3 || fields[0].chars().count() != 1 {
+                return Err(format!(
+                    "legend line {}: expected \"symbol name cost\", found \"{}\"",
+                    index + 1,
+                    line
+                ));
+            }
+
+            let symbol = fields[0].chars().next().unwrap();
+            if terrains.iter().any(|t| t.symbol == symbol) {
+                return Err(format!(
+                    "legend line {}: symbol '{}' is defined twice",
+                    index + 1,
+                    symbol
+                ));
+            }
+            let cost = fields[2]
+                .parse()
+                .map_err(|_| format!("legend line {}: invalid cost {}", index + 1, fields[2]))?;
+
+            terrains.push(Terrain {
+                symbol,
+                name: fields[1].to_string(),
+                cost,
+            });
+        }
+
+        if terrains.is_empty() {
+            return Err("legend defines no terrain".to_string());
+        }
+
+        Ok(Legend { terrains })
+    }
+
+    fn parse_area(&self, input: &str) -> Result<Vec<Vec<Square>>, String> {
+        let mut area = Vec::new();
+
+        for (y, line) in input.lines().enumerate() {
+            let mut row = Vec::new();
+            for (x, ch) in line.chars().enumerate() {
+                match self.terrains.iter().position(|t| t.symbol == ch) {
+                    Some(index) => row.push(Square(index)),
+                    None => {
+                        return Err(format!(
+                            "line {}, column {}: unknown terrain '{}'",
+                            y + 1,
+                            x + 1,
+                            ch
+                        ))
+                    }
+                }
+            }
+            if row.is_empty() {
+                return Err(format!("line {}: empty row", y + 1));
+            }
+            area.push(row);
+        }
+
+        Ok(area)
+    }
+
+    fn terrain(&self, square: &Square) -> &Terrain {
+        &self.terrains[square.0]
+    }
+}
+
+// Leftward travel uses a negative x offset; the pattern repeats in both directions
+fn calculate_encounters(
+    legend: &Legend,
+    area: &[Vec<Square>],
+    x_offset: isize,
+    y_offset: usize,
+) -> Encounters {
+    let mut encounters = Encounters {
+        cost: 0,
+        counts: vec![0; legend.terrains.len()],
+    };
+
+    for (x, y) in trace_path(area, x_offset, y_offset) {
+        let square = &area[y][x.rem_euclid(area[y].len() as isize) as usize];
+        encounters.cost += u64::from(legend.terrain(square).cost);
+        encounters.counts[square.0] += 1;
+    }
+
+    encounters
+}
+
+// Positions visited on the way down, in unwrapped coordinates
+fn trace_path(area: &[Vec<Square>], x_offset: isize, y_offset: usize) -> Vec<(isize, usize)> {
+    assert!(y_offset > 0, "y offset must be positive");
+
+    let mut path = Vec::new();
+    let mut x: isize = 0;
     let mut y = 0;
-    let mut tree_encounters = 0;
 
     while y < area.len() {
-        if area[y][x % area[0].len()] == Square::Tree {
-            tree_encounters += 1;
-        }
+        path.push((x, y));
         x += x_offset;
         y += y_offset;
     }
 
-    tree_encounters
+    path
+}
+
+// Marks hits as in the puzzle text: O for free squares, X for costly ones
+fn render_path(
+    legend: &Legend,
+    area: &[Vec<Square>],
+    x_offset: isize,
+    y_offset: usize,
+    colour: bool,
+) -> String {
+    let path = trace_path(area, x_offset, y_offset);
+    let width = area.iter().map(|row| row.len()).max().unwrap_or(0) as isize;
+    if width == 0 {
+        return String::new();
+    }
+
+    let min_x = path.iter().map(|&(x, _)| x).min().unwrap_or(0);
+    let max_x = path.iter().map(|&(x, _)| x).max().unwrap_or(0);
+    let left = min_x.div_euclid(width) * width;
+    let right = (max_x.div_euclid(width) + 1) * width;
+
+    let mut output = String::new();
+    let mut steps = path.iter().peekable();
+
+    for (y, row) in area.iter().enumerate() {
+        let hit = match steps.peek() {
+            Some(&&(x, step_y)) if step_y == y => {
+                steps.next();
+                Some(x)
+            }
+            _ => None,
+        };
+
+        for x in left..right {
+            let terrain = legend.terrain(&row[x.rem_euclid(row.len() as isize) as usize]);
+            if hit == Some(x) {
+                let mark = match (terrain.cost > 0, colour) {
+                    (true, true) => "\x1b[1;31mX\x1b[0m",
+                    (true, false) => "X",
+                    (false, true) => "\x1b[1;32mO\x1b[0m",
+                    (false, false) => "O",
+                };
+                output.push_str(mark);
+            } else {
+                output.push(terrain.symbol);
+            }
+        }
+        output.push('\n');
+    }
+
+    output
+}
+
+fn parse_slope(text: &str) -> (isize, usize) {
+    let parts: Vec<&str> = text.split(',').collect();
+    if parts.len() != 2 {
+        panic!("invalid slope {}, expected dx,dy", text);
+    }
+    let x_offset = parts[0]
+        .parse()
+        .unwrap_or_else(|_| panic!("invalid x offset in slope {}", text));
+    let y_offset = parts[1]
+        .parse()
+        .unwrap_or_else(|_| panic!("invalid y offset in slope {}", text));
+    if y_offset == 0 {
+        panic!("invalid slope {}, dy must be positive", text);
+    }
+
+    (x_offset, y_offset)
+}
+
+// Searches dx in -max_x..=max_x and dy in 1..=max_y; ties go to the first slope found
+fn find_best_slope(
+    legend: &Legend,
+    area: &[Vec<Square>],
+    max_x: isize,
+    max_y: usize,
+) -> ((isize, usize), Encounters) {
+    assert!(max_y > 0, "max y offset must be positive");
+
+    let mut best: Option<((isize, usize), Encounters)> = None;
+
+    for y_offset in 1..=max_y {
+        for x_offset in -max_x..=max_x {
+            let encounters = calculate_encounters(legend, area, x_offset, y_offset);
+            match &best {
+                Some((_, fewest)) if fewest.cost <= encounters.cost => {}
+                _ => best = Some(((x_offset, y_offset), encounters)),
+            }
+        }
+    }
+
+    best.unwrap()
+}
+
+fn print_encounters(
+    legend: &Legend,
+    (x_offset, y_offset): (isize, usize),
+    encounters: &Encounters,
+) {
+    let breakdown: Vec<String> = legend
+        .terrains
+        .iter()
+        .zip(encounters.counts.iter())
+        .filter(|(_, &count)| count > 0)
+        .map(|(terrain, count)| format!("{} {}", count, terrain.name))
+        .collect();
+    println!(
+        "Slope ({}, {}): total cost {} ({})",
+        x_offset,
+        y_offset,
+        encounters.cost,
+        breakdown.join(", ")
+    );
+}
+
+// None once the product no longer fits in a u64
+fn cost_product<I: IntoIterator<Item = u64>>(costs: I) -> Option<u64> {
+    costs
+        .into_iter()
+        .try_fold(1u64, |product, cost| product.checked_mul(cost))
+}
+
+fn report_slopes(legend: &Legend, area: &[Vec<Square>], slopes: &[(isize, usize)]) {
+    let mut costs = Vec::new();
+
+    for &(x_offset, y_offset) in slopes.iter() {
+        let encounters = calculate_encounters(legend, area, x_offset, y_offset);
+        print_encounters(legend, (x_offset, y_offset), &encounters);
+        costs.push(encounters.cost);
+    }
+
+    match cost_product(costs) {
+        Some(product) => println!("Product of costs: {}", product),
+        None => println!("Product of costs: too large for a 64-bit integer"),
+    }
 }
 
 fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let custom_legend = args.iter().any(|x| x == "--legend");
+
+    let legend = match args.iter().position(|x| x == "--legend") {
+        Some(index) => {
+            if index + 1 >= args.len() {
+                panic!("usage: --legend FILE");
+            }
+            let path = args.remove(index + 1);
+            args.remove(index);
+            let text = fs::read_to_string(&path).unwrap();
+            Legend::parse(&text).unwrap_or_else(|e| panic!("{}: {}", path, e))
+        }
+        None => Legend::new(),
+    };
+
     let mut input = String::new();
     stdin().read_to_string(&mut input).unwrap();
 
-    let area: Vec<Vec<Square>> = input
-        .lines()
-        .map(|x| {
-            x.chars()
-                .map(|z| if z == '#' { Square::Tree } else { Square::Open })
-                .collect()
-        })
-        .collect();
+    let area = legend
+        .parse_area(&input)
+        .unwrap_or_else(|e| panic!("{}", e));
 
-    println!(
-        "Part 1: You would encounter {} trees",
-        calculate_encounters(&area, 3, 1)
-    );
-    let tree_encounters = calculate_encounters(&area, 1, 1)
-        * calculate_encounters(&area, 3, 1)
-        * calculate_encounters(&area, 5, 1)
-        * calculate_encounters(&area, 7, 1)
-        * calculate_encounters(&area, 1, 2);
-    println!("Part 2: You would encounter {} trees", tree_encounters);
+    match args.first().map(|x| x.as_str()) {
+        Some("--slopes") => {
+            let slopes: Vec<(isize, usize)> = args[1..].iter().map(|x| parse_slope(x)).collect();
+            report_slopes(&legend, &area, &slopes);
+        }
+        Some("--search") => {
+            if args.len() != 3 {
+                panic!("usage: --search MAX_DX MAX_DY");
+            }
+            let max_x: isize = args[1].parse().unwrap();
+            let max_y: usize = args[2].parse().unwrap();
+            let (slope, encounters) = find_best_slope(&legend, &area, max_x.abs(), max_y);
+            print!("Best: ");
+            print_encounters(&legend, slope, &encounters);
+        }
+        Some("--render") => {
+            if args.len() != 2 && !(args.len() == 4 && args[2] == "--output") {
+                panic!("usage: --render DX,DY [--output FILE]");
+            }
+            let (x_offset, y_offset) = parse_slope(&args[1]);
+            match args.get(3) {
+                Some(path) => {
+                    let rendered = render_path(&legend, &area, x_offset, y_offset, false);
+                    fs::write(path, rendered).unwrap();
+                }
+                None => {
+                    let colour = stdout().is_terminal();
+                    print!(
+                        "{}",
+                        render_path(&legend, &area, x_offset, y_offset, colour)
+                    );
+                }
+            }
+            let encounters = calculate_encounters(&legend, &area, x_offset, y_offset);
+            print_encounters(&legend, (x_offset, y_offset), &encounters);
+        }
+        Some(arg) => panic!("unknown argument {}", arg),
+        // A custom legend weighs terrains by cost, so report costs rather than trees
+        None if custom_legend => {
+            print!("Part 1: ");
+            print_encounters(&legend, (3, 1), &calculate_encounters(&legend, &area, 3, 1));
+            println!("Part 2:");
+            report_slopes(&legend, &area, &[(1, 1), (3, 1), (5, 1), (7, 1), (1, 2)]);
+        }
+        None => {
+            println!(
+                "Part 1: You would encounter {} trees",
+                calculate_encounters(&legend, &area, 3, 1).cost
+            );
+            let tree_encounters =
+                [(1, 1), (3, 1), (5, 1), (7, 1), (1, 2)]
+                    .iter()
+                    .map(|&(x_offset, y_offset)| {
+                        calculate_encounters(&legend, &area, x_offset, y_offset).cost
+                    });
+            match cost_product(tree_encounters) {
+                Some(product) => println!("Part 2: You would encounter {} trees", product),
+                None => println!(
+                    "Part 2: The product of tree encounters is too large for a 64-bit integer"
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn legend_errors() {
+        use crate::Legend;
+
+        let duplicate = Legend::parse(". open 0\n~ ice 2\n~ water 3\n").unwrap_err();
+        assert_eq!(duplicate, "legend line 3: symbol '~' is defined twice");
+        let bad_cost = Legend::parse(". open 0\n~ ice slippery\n").unwrap_err();
+        assert_eq!(bad_cost, "legend line 2: invalid cost slippery");
+
+        let unknown = Legend::new().parse_area("..#\n.x.\n").unwrap_err();
+        assert_eq!(unknown, "line 2, column 2: unknown terrain 'x'");
+    }
+
+    #[test]
+    fn weighted_cost() {
+        use crate::{calculate_encounters, Legend};
+
+        let legend = Legend::parse(". open 0\n# tree 1\n~ ice 2\n").unwrap();
+        let area = legend.parse_area("...\n.~.\n..#\n").unwrap();
+        let encounters = calculate_encounters(&legend, &area, 1, 1);
+
+        assert_eq!(encounters.cost, 3);
+        assert_eq!(encounters.counts, vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn leftward_slopes() {
+        use crate::{calculate_encounters, find_best_slope, Legend};
+
+        let legend = Legend::new();
+        let area = legend.parse_area("...\n..#\n.#.\n").unwrap();
+
+        assert_eq!(calculate_encounters(&legend, &area, -1, 1).cost, 2);
+        assert_eq!(calculate_encounters(&legend, &area, 1, 1).cost, 0);
+
+        let (slope, encounters) = find_best_slope(&legend, &area, 1, 1);
+        assert_eq!(slope, (0, 1));
+        assert_eq!(encounters.cost, 0);
+    }
+
+    #[test]
+    fn product_overflow() {
+        use crate::cost_product;
+
+        assert_eq!(cost_product(vec![2, 7, 3]), Some(42));
+        assert_eq!(
+            cost_product(vec![u64::from(u32::MAX); 2]),
+            Some(18446744065119617025)
+        );
+        assert_eq!(cost_product(vec![u64::from(u32::MAX); 3]), None);
+    }
 }
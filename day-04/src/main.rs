@@ -1,126 +1,594 @@
 use regex::Regex;
+use serde::Deserialize;
 use std::collections::HashMap;
-use std::io::{stdin, Read};
+use std::env;
+use std::fs;
+use std::io::{stdin, BufRead, Lines};
+use std::time::Instant;
 
-fn validate_passport_part_1(passport: &HashMap<&str, &str>) -> bool {
-    passport.contains_key("byr")
-        && passport.contains_key("iyr")
-        && passport.contains_key("eyr")
-        && passport.contains_key("hgt")
-        && passport.contains_key("hcl")
-        && passport.contains_key("ecl")
-        && passport.contains_key("pid")
+// Schema file layout, as written in TOML or JSON
+#[derive(Debug, Deserialize)]
+struct SchemaSpec {
+    field: Vec<FieldSpec>,
 }
 
-fn validate_passport_part_2(passport: &HashMap<&str, &str>) -> bool {
-    let mut byr_bool = false;
-    if let Ok(byr) = passport["byr"].parse::<u16>() {
-        if byr >= 1920 && byr <= 2002 {
-            byr_bool = true;
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FieldSpec {
+    name: String,
+    required: Option<bool>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    min: Option<u32>,
+    max: Option<u32>,
+    units: Option<Vec<UnitSpec>>,
+    pattern: Option<String>,
+    values: Option<Vec<String>>,
+    lowercase: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UnitSpec {
+    unit: String,
+    min: u32,
+    max: u32,
+    factor: Option<f64>,
+}
+
+// The first unit of a measurement is canonical; factor converts into it
+#[derive(Debug)]
+struct Unit {
+    name: String,
+    min: u32,
+    max: u32,
+    factor: f64,
+}
+
+#[derive(Debug)]
+enum Check {
+    Any,
+    Year { min: u32, max: u32 },
+    Measurement(Vec<Unit>),
+    Pattern(Regex),
+    OneOf(Vec<String>),
+}
+
+#[derive(Debug)]
+struct Field {
+    name: String,
+    required: bool,
+    lowercase: bool,
+    check: Check,
+}
+
+#[derive(Debug)]
+enum Problem {
+    Missing(String),
+    Invalid(String, String),
+    Duplicate(String),
+}
+
+// Fields of one passport and the input lines it was read from
+#[derive(Debug)]
+struct Passport {
+    first_line: usize,
+    last_line: usize,
+    fields: HashMap<String, String>,
+    duplicates: Vec<String>,
+}
+
+// Yields passports one at a time, so only the current record is held in memory
+struct PassportReader<R> {
+    lines: Lines<R>,
+    line_number: usize,
+}
+
+// A schema compiled once into checks that can be applied to every passport
+#[derive(Debug)]
+struct Schema {
+    fields: Vec<Field>,
+}
+
+impl Check {
+    fn compile(spec: &FieldSpec) -> Result<Check, String> {
+        let missing = |key: &str| {
+            format!(
+                "field {}: type {} requires {}",
+                spec.name,
+                spec.kind.as_deref().unwrap_or(""),
+                key
+            )
+        };
+
+        match spec.kind.as_deref() {
+            None => Ok(Check::Any),
+            Some("year") => Ok(Check::Year {
+                min: spec.min.ok_or_else(|| missing("min"))?,
+                max: spec.max.ok_or_else(|| missing("max"))?,
+            }),
+            Some("measurement") => {
+                let units = spec.units.as_ref().ok_or_else(|| missing("units"))?;
+                Ok(Check::Measurement(
+                    units
+                        .iter()
+                        .map(|u| Unit {
+                            name: u.unit.clone(),
+                            min: u.min,
+                            max: u.max,
+                            factor: u.factor.unwrap_or(1.0),
+                        })
+                        .collect(),
+                ))
+            }
+            Some("regex") => {
+                let pattern = spec.pattern.as_ref().ok_or_else(|| missing("pattern"))?;
+                let rx = Regex::new(pattern)
+                    .map_err(|e| format!("field {}: invalid pattern: {}", spec.name, e))?;
+                Ok(Check::Pattern(rx))
+            }
+            Some("enum") => {
+                let values = spec.values.as_ref().ok_or_else(|| missing("values"))?;
+                Ok(Check::OneOf(values.clone()))
+            }
+            Some(kind) => Err(format!("field {}: unknown type {}", spec.name, kind)),
         }
     }
 
-    let mut iyr_bool = false;
-    if let Ok(iyr) = passport["iyr"].parse::<u16>() {
-        if iyr >= 2010 && iyr <= 2020 {
-            iyr_bool = true;
-        }
+    fn measure<'a>(units: &'a [Unit], value: &str) -> Option<(&'a Unit, u32)> {
+        units.iter().find_map(|unit| {
+            value
+                .strip_suffix(unit.name.as_str())
+                .filter(|amount| amount.chars().all(|ch| ch.is_ascii_digit()))
+                .and_then(|amount| amount.parse::<u32>().ok())
+                .map(|amount| (unit, amount))
+        })
     }
 
-    let mut eyr_bool = false;
-    if let Ok(eyr) = passport["eyr"].parse::<u16>() {
-        if eyr >= 2020 && eyr <= 2030 {
-            eyr_bool = true;
+    fn check(&self, value: &str) -> Result<(), String> {
+        match self {
+            Check::Any => Ok(()),
+            Check::Year { min, max } => {
+                let year = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|_| value.len() == 4)
+                    .ok_or_else(|| format!("{} is not a four-digit year", value))?;
+                if (*min..=*max).contains(&year) {
+                    Ok(())
+                } else {
+                    Err(format!("{} out of {}-{} range", value, min, max))
+                }
+            }
+            Check::Measurement(units) => match Check::measure(units, value) {
+                Some((unit, amount)) if (unit.min..=unit.max).contains(&amount) => Ok(()),
+                Some((unit, _)) => Err(format!(
+                    "{} out of {}-{}{} range",
+                    value, unit.min, unit.max, unit.name
+                )),
+                None => {
+                    let names: Vec<&str> = units.iter().map(|u| u.name.as_str()).collect();
+                    Err(format!(
+                        "{} is not a measurement in {}",
+                        value,
+                        names.join(", ")
+                    ))
+                }
+            },
+            Check::Pattern(rx) => {
+                if rx.is_match(value) {
+                    Ok(())
+                } else {
+                    Err(format!("{} does not match {}", value, rx.as_str()))
+                }
+            }
+            Check::OneOf(values) => {
+                if values.iter().any(|x| x == value) {
+                    Ok(())
+                } else {
+                    Err(format!("{} is not one of {}", value, values.join(", ")))
+                }
+            }
         }
     }
+}
 
-    let mut hgt_bool = false;
-    let rx = Regex::new(r"^([0-9]{3})cm$").unwrap();
-    if let Some(cap_cm) = rx.captures(passport["hgt"]) {
-        if let Ok(cms) = cap_cm[1].parse::<u16>() {
-            if cms >= 150 && cms <= 193 {
-                hgt_bool = true;
+impl Schema {
+    fn parse(text: &str, json: bool) -> Result<Schema, String> {
+        let spec: SchemaSpec = if json {
+            serde_json::from_str(text).map_err(|e| e.to_string())?
+        } else {
+            toml::from_str(text).map_err(|e| e.to_string())?
+        };
+
+        let mut fields: Vec<Field> = Vec::new();
+        for field_spec in spec.field.iter() {
+            if fields.iter().any(|f| f.name == field_spec.name) {
+                return Err(format!("field {} is defined twice", field_spec.name));
             }
+            fields.push(Field {
+                name: field_spec.name.clone(),
+                required: field_spec.required.unwrap_or(true),
+                lowercase: field_spec.lowercase.unwrap_or(false),
+                check: Check::compile(field_spec)?,
+            });
         }
-    } else {
-        let rx = Regex::new(r"^([0-9]{2})in$").unwrap();
-        if let Some(cap_in) = rx.captures(passport["hgt"]) {
-            if let Ok(ins) = cap_in[1].parse::<u16>() {
-                if ins >= 59 && ins <= 76 {
-                    hgt_bool = true;
+
+        Ok(Schema { fields })
+    }
+
+    fn has_required_fields(&self, passport: &Passport) -> bool {
+        self.fields
+            .iter()
+            .filter(|f| f.required)
+            .all(|f| passport.fields.contains_key(f.name.as_str()))
+    }
+
+    fn is_valid(&self, passport: &Passport) -> bool {
+        self.has_required_fields(passport)
+            && passport.duplicates.is_empty()
+            && self.fields.iter().all(|f| {
+                passport
+                    .fields
+                    .get(f.name.as_str())
+                    .is_none_or(|value| f.check.check(value).is_ok())
+            })
+    }
+
+    fn problems(&self, passport: &Passport) -> Vec<Problem> {
+        let mut problems: Vec<Problem> = passport
+            .duplicates
+            .iter()
+            .map(|name| Problem::Duplicate(name.clone()))
+            .collect();
+
+        for field in self.fields.iter() {
+            match passport.fields.get(field.name.as_str()) {
+                None if field.required => problems.push(Problem::Missing(field.name.clone())),
+                None => {}
+                Some(value) => {
+                    if let Err(reason) = field.check.check(value) {
+                        problems.push(Problem::Invalid(field.name.clone(), reason));
+                    }
                 }
             }
         }
+
+        problems
     }
 
-    let rx = Regex::new(r"^#[0-9|a-f]{6}$").unwrap();
-    let hcl_bool = rx.is_match(passport["hcl"]);
+    // Schema fields come first in schema order, then any others alphabetically
+    fn normalise(&self, passport: &Passport) -> Result<String, String> {
+        if !passport.duplicates.is_empty() {
+            return Err(format!(
+                "duplicate fields {}",
+                passport.duplicates.join(", ")
+            ));
+        }
 
-    let ecl_bool = matches!(
-        passport["ecl"],
-        "amb" | "blu" | "brn" | "gry" | "grn" | "hzl" | "oth"
-    );
+        let mut pairs = Vec::new();
+        for field in self.fields.iter() {
+            if let Some(value) = passport.fields.get(field.name.as_str()) {
+                pairs.push(format!("{}:{}", field.name, field.normalise(value)));
+            }
+        }
 
-    let rx = Regex::new(r"^[0-9]{9}$").unwrap();
-    let pid_bool = rx.is_match(passport["pid"]);
+        let mut others: Vec<(&String, &String)> = passport
+            .fields
+            .iter()
+            .filter(|(name, _)| !self.fields.iter().any(|f| &f.name == *name))
+            .collect();
+        others.sort_unstable();
+        for (name, value) in others {
+            pairs.push(format!("{}:{}", name, value));
+        }
 
-    byr_bool && iyr_bool && eyr_bool && hgt_bool && hcl_bool && ecl_bool && pid_bool
+        Ok(pairs.join(" "))
+    }
 }
 
-fn main() {
-    let mut input = String::new();
-    stdin().read_to_string(&mut input).unwrap();
-
-    // Consolidate multiline passports
-
-    let (mut passport_lines, last_passport_line) =
-        input
-            .lines()
-            .fold((Vec::new(), String::new()), |(mut acc, mut pp), x| {
-                if x.is_empty() {
-                    acc.push(pp);
-                    (acc, String::new())
-                } else {
-                    if !pp.is_empty() {
-                        pp.push(' ')
-                    };
-                    pp.push_str(&x);
-                    (acc, pp)
+impl Field {
+    fn normalise(&self, value: &str) -> String {
+        let value = if self.lowercase {
+            value.to_lowercase()
+        } else {
+            value.to_string()
+        };
+
+        match &self.check {
+            Check::Measurement(units) => match Check::measure(units, &value) {
+                Some((unit, amount)) => {
+                    let canonical = (f64::from(amount) * unit.factor).round();
+                    format!("{}{}", canonical, units[0].name)
                 }
+                None => value,
+            },
+            _ => value,
+        }
+    }
+}
+
+impl Problem {
+    fn cause(&self) -> String {
+        match self {
+            Problem::Missing(name) => format!("{} missing", name),
+            Problem::Invalid(name, _) => format!("{} invalid", name),
+            Problem::Duplicate(name) => format!("{} duplicated", name),
+        }
+    }
+}
+
+impl<R: BufRead> PassportReader<R> {
+    fn new(reader: R) -> PassportReader<R> {
+        PassportReader {
+            lines: reader.lines(),
+            line_number: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PassportReader<R> {
+    type Item = Passport;
+
+    fn next(&mut self) -> Option<Passport> {
+        let mut current: Option<Passport> = None;
+
+        for line in &mut self.lines {
+            let line = line.unwrap();
+            self.line_number += 1;
+            let line_number = self.line_number;
+
+            if line.trim().is_empty() {
+                if current.is_some() {
+                    break;
+                }
+                continue;
+            }
+
+            let passport = current.get_or_insert_with(|| Passport {
+                first_line: line_number,
+                last_line: line_number,
+                fields: HashMap::new(),
+                duplicates: Vec::new(),
             });
-    passport_lines.push(last_passport_line);
+            passport.last_line = line_number;
 
-    // Create passport hash maps
+            for field in line.split_whitespace() {
+                let (key, value) = field.split_once(':').unwrap_or((field, ""));
+                if passport
+                    .fields
+                    .insert(key.to_string(), value.to_string())
+                    .is_some()
+                    && !passport.duplicates.iter().any(|x| x == key)
+                {
+                    passport.duplicates.push(key.to_string());
+                }
+            }
+        }
 
-    let mut passports = Vec::new();
+        current
+    }
+}
 
-    for passport_line in passport_lines.iter() {
-        let mut hm = HashMap::new();
+fn print_report(schema: &Schema, passports: impl Iterator<Item = Passport>) -> usize {
+    let mut causes: HashMap<String, usize> = HashMap::new();
+    let mut passport_counter = 0;
+    let mut invalid_counter = 0;
 
-        let fields: Vec<&str> = passport_line.split(' ').collect();
+    for passport in passports {
+        passport_counter += 1;
+        let problems = schema.problems(&passport);
+        if problems.is_empty() {
+            println!(
+                "Lines {}-{}: valid",
+                passport.first_line, passport.last_line
+            );
+            continue;
+        }
 
-        for field in fields.iter() {
-            let kv: Vec<&str> = field.split(':').collect();
-            hm.insert(kv[0], kv[1]);
+        invalid_counter += 1;
+        println!(
+            "Lines {}-{}: invalid",
+            passport.first_line, passport.last_line
+        );
+        for problem in problems.iter() {
+            match problem {
+                Problem::Missing(name) => println!("  {} missing", name),
+                Problem::Invalid(name, reason) => println!("  {} {}", name, reason),
+                Problem::Duplicate(name) => println!("  {} appears more than once", name),
+            }
+            *causes.entry(problem.cause()).or_default() += 1;
         }
+    }
+
+    println!();
+    println!(
+        "Summary: {} of {} passports are invalid",
+        invalid_counter, passport_counter
+    );
+    let mut causes: Vec<(String, usize)> = causes.into_iter().collect();
+    causes.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    for (cause, count) in causes.iter() {
+        println!("  {:>6}  {}", count, cause);
+    }
 
-        passports.push(hm);
+    passport_counter
+}
+
+fn print_normalised(schema: &Schema, passports: impl Iterator<Item = Passport>) -> usize {
+    let mut passport_counter = 0;
+
+    for passport in passports {
+        match schema.normalise(&passport) {
+            Ok(line) => {
+                if passport_counter > 0 {
+                    println!();
+                }
+                println!("{}", line);
+                passport_counter += 1;
+            }
+            Err(e) => eprintln!(
+                "Lines {}-{}: {}",
+                passport.first_line, passport.last_line, e
+            ),
+        }
     }
 
-    // Validate passports
+    passport_counter
+}
 
-    let mut valid_counter_part_1 = 0;
-    let mut valid_counter_part_2 = 0;
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut schema_path = None;
+    let mut report = false;
+    let mut throughput = false;
+    let mut normalise = false;
 
-    for passport in passports.iter() {
-        if validate_passport_part_1(&passport) {
-            valid_counter_part_1 += 1;
-            if validate_passport_part_2(&passport) {
-                valid_counter_part_2 += 1;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--schema" => {
+                schema_path = Some(iter.next().expect("usage: --schema FILE"));
             }
+            "--report" => {
+                report = true;
+            }
+            "--normalise" => {
+                normalise = true;
+            }
+            "--throughput" => {
+                throughput = true;
+            }
+            _ => panic!("unknown argument {}", arg),
         }
     }
 
-    println!("Part 1: there are {} valid passports", valid_counter_part_1);
-    println!("Part 2: there are {} valid passports", valid_counter_part_2);
+    let schema = match schema_path {
+        Some(path) => {
+            let text = fs::read_to_string(path).unwrap();
+            Schema::parse(&text, path.ends_with(".json"))
+                .unwrap_or_else(|e| panic!("{}: {}", path, e))
+        }
+        None => Schema::parse(include_str!("passport.toml"), false)
+            .unwrap_or_else(|e| panic!("passport.toml: {}", e)),
+    };
+
+    let start = Instant::now();
+    let passports = PassportReader::new(stdin().lock());
+
+    let passport_counter = if report {
+        print_report(&schema, passports)
+    } else if normalise {
+        print_normalised(&schema, passports)
+    } else {
+        // Validate passports
+
+        let mut passport_counter = 0;
+        let mut valid_counter_part_1 = 0;
+        let mut valid_counter_part_2 = 0;
+
+        for passport in passports {
+            passport_counter += 1;
+            if schema.has_required_fields(&passport) {
+                valid_counter_part_1 += 1;
+                if schema.is_valid(&passport) {
+                    valid_counter_part_2 += 1;
+                }
+            }
+        }
+
+        println!("Part 1: there are {} valid passports", valid_counter_part_1);
+        println!("Part 2: there are {} valid passports", valid_counter_part_2);
+
+        passport_counter
+    };
+
+    if throughput {
+        let seconds = start.elapsed().as_secs_f64();
+        eprintln!(
+            "Validated {} passports in {:.3}s ({:.0} passports/s)",
+            passport_counter,
+            seconds,
+            passport_counter as f64 / seconds
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn incomplete_passport() {
+        use crate::{Passport, PassportReader, Schema};
+
+        let schema = Schema::parse(include_str!("passport.toml"), false).unwrap();
+        let input = "iyr:2013 ecl:amb cid:350 eyr:2023 pid:028048884\nhgt:190in";
+        let passports: Vec<Passport> = PassportReader::new(input.as_bytes()).collect();
+        assert!(!schema.is_valid(&passports[0]));
+
+        let problems: Vec<String> = schema
+            .problems(&passports[0])
+            .iter()
+            .map(|x| x.cause())
+            .collect();
+        assert_eq!(problems, vec!["byr missing", "hgt invalid", "hcl missing"]);
+    }
+
+    #[test]
+    fn schema_files() {
+        use crate::Schema;
+
+        let json = r#"{"field": [{"name": "byr", "type": "year", "min": 1920, "max": 2002}]}"#;
+        let schema = Schema::parse(json, true).unwrap();
+        assert_eq!(schema.fields[0].name, "byr");
+
+        let unknown = Schema::parse("[[field]]\nname = \"eye\"\ntype = \"colour\"\n", false);
+        assert_eq!(unknown.unwrap_err(), "field eye: unknown type colour");
+
+        let bad_pattern = "[[field]]\nname = \"pid\"\ntype = \"regex\"\npattern = \"(\"\n";
+        assert!(Schema::parse(bad_pattern, false)
+            .unwrap_err()
+            .starts_with("field pid: invalid pattern"));
+    }
+
+    #[test]
+    fn reader_records() {
+        use crate::{Passport, PassportReader};
+
+        let input = "a:1\nb:2\n\n  \n\nc:3\n   \nd:4 e:5\n";
+        let passports: Vec<Passport> = PassportReader::new(input.as_bytes()).collect();
+        let lines: Vec<(usize, usize, usize)> = passports
+            .iter()
+            .map(|x| (x.first_line, x.last_line, x.fields.len()))
+            .collect();
+
+        assert_eq!(lines, vec![(1, 2, 2), (6, 6, 1), (8, 8, 2)]);
+    }
+
+    #[test]
+    fn normalise_round_trip() {
+        use crate::{Passport, PassportReader, Schema};
+
+        let schema = Schema::parse(include_str!("passport.toml"), false).unwrap();
+        let input =
+            "zzz:9 hgt:60in ecl:amb cid:1 hcl:#ABCDEF\nbyr:1980 iyr:2015 eyr:2025 pid:000000001";
+        let passport = PassportReader::new(input.as_bytes()).next().unwrap();
+        let normalised = schema.normalise(&passport).unwrap();
+        assert_eq!(
+            normalised,
+            "byr:1980 iyr:2015 eyr:2025 hgt:152cm hcl:#abcdef ecl:amb pid:000000001 cid:1 zzz:9"
+        );
+
+        let reread: Vec<Passport> = PassportReader::new(normalised.as_bytes()).collect();
+        assert_eq!(reread.len(), 1);
+        assert!(schema.is_valid(&reread[0]));
+        assert_eq!(schema.normalise(&reread[0]).unwrap(), normalised);
+
+        let duplicated = PassportReader::new("byr:1980 byr:1981".as_bytes())
+            .next()
+            .unwrap();
+        assert_eq!(
+            schema.normalise(&duplicated).unwrap_err(),
+            "duplicate fields byr"
+        );
+    }
 }
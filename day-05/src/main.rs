@@ -1,42 +1,651 @@
-use itertools::zip;
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::fs;
 use std::io::{stdin, Read};
+use std::str::FromStr;
 
-fn main() {
-    let mut input = String::new();
-    stdin().read_to_string(&mut input).unwrap();
+#[derive(Debug, PartialEq)]
+enum PassError {
+    Length(usize, usize),
+    Character(usize, char),
+    Row(u16),
+    ExcludedRow(u16),
+    Column(u16),
+    SeatId(u32),
+}
 
-    let passes: Vec<&str> = input.lines().collect();
-    dbg!(&passes);
+impl fmt::Display for PassError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PassError::Length(expected, found) => {
+                write!(f, "expected {} characters, found {}", expected, found)
+            }
+            PassError::Character(position, ch) => {
+                write!(f, "invalid character {:?} at position {}", ch, position + 1)
+            }
+            PassError::Row(row) => write!(f, "row {} is out of range", row),
+            PassError::ExcludedRow(row) => write!(f, "row {} does not exist", row),
+            PassError::Column(column) => write!(f, "column {} is out of range", column),
+            PassError::SeatId(seat_id) => write!(f, "seat ID {} is out of range", seat_id),
+        }
+    }
+}
 
-    let seat_id_binary_strings: Vec<String> = passes
-        .iter()
-        .map(|x| {
-            x.chars()
-                .map(|y| match y {
-                    'B' | 'R' => '1',
-                    'F' | 'L' => '0',
-                    _ => panic!("invalid input {}", y),
-                })
-                .collect()
-        })
-        .collect();
-    dbg!(&seat_id_binary_strings);
+// How the seat within a row is written on the pass
+#[derive(Debug)]
+enum ColumnScheme {
+    Binary,
+    Letters(Vec<char>),
+}
+
+#[derive(Debug)]
+struct Aircraft {
+    rows: u16,
+    seats: u16,
+    scheme: ColumnScheme,
+    aisles: Vec<u16>,
+    excluded_rows: HashSet<u16>,
+}
+
+#[derive(Debug, PartialEq)]
+enum SeatStatus {
+    Occupied,
+    Yours,
+    MissingFront,
+    MissingBack,
+    Vacant,
+}
+
+#[derive(Debug, PartialEq)]
+struct BoardingPass {
+    row: u16,
+    column: u16,
+}
+
+fn bits_for(count: u16) -> usize {
+    (16 - count.saturating_sub(1).leading_zeros()) as usize
+}
+
+impl Aircraft {
+    // The puzzle's aircraft: 128 rows of 8 seats, with columns written as L/R
+    fn new() -> Aircraft {
+        Aircraft {
+            rows: 128,
+            seats: 8,
+            scheme: ColumnScheme::Binary,
+            aisles: Vec::new(),
+            excluded_rows: HashSet::new(),
+        }
+    }
+
+    // One setting per line: "rows 30", "seats 6", "letters ABC DEF", "exclude 13"
+    fn parse(text: &str) -> Result<Aircraft, String> {
+        let mut aircraft = Aircraft::new();
+        let mut seats = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let value = value.trim();
+            let number = |text: &str| {
+                text.parse::<u16>()
+                    .map_err(|_| format!("profile line {}: invalid number {}", index + 1, text))
+            };
+
+            match key {
+                "rows" => aircraft.rows = number(value)?,
+                "seats" => seats = Some(number(value)?),
+                "letters" => {
+                    let mut letters = Vec::new();
+                    for ch in value.chars() {
+                        if ch == ' ' {
+                            aircraft.aisles.push(letters.len() as u16);
+                            continue;
+                        }
+                        if letters.contains(&ch) {
+                            return Err(format!(
+                                "profile line {}: seat letter {} is repeated",
+                                index + 1,
+                                ch
+                            ));
+                        }
+                        letters.push(ch);
+                    }
+                    aircraft.scheme = ColumnScheme::Letters(letters);
+                }
+                "exclude" => {
+                    for row in value.split_whitespace() {
+                        aircraft.excluded_rows.insert(number(row)?);
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                        "profile line {}: unknown setting {}",
+                        index + 1,
+                        key
+                    ))
+                }
+            }
+        }
+
+        aircraft.seats = match (&aircraft.scheme, seats) {
+            (ColumnScheme::Letters(letters), Some(seats)) if seats as usize != letters.len() => {
+                return Err(format!(
+                    "{} seats per row do not match {} seat letters",
+                    seats,
+                    letters.len()
+                ))
+            }
+            (ColumnScheme::Letters(letters), _) => letters.len() as u16,
+            (ColumnScheme::Binary, Some(seats)) => seats,
+            (ColumnScheme::Binary, None) => aircraft.seats,
+        };
+        if aircraft.rows == 0 || aircraft.seats == 0 {
+            return Err("an aircraft needs at least one row and one seat".to_string());
+        }
+
+        Ok(aircraft)
+    }
+
+    fn row_bits(&self) -> usize {
+        bits_for(self.rows)
+    }
+
+    fn code_length(&self) -> usize {
+        match &self.scheme {
+            ColumnScheme::Binary => self.row_bits() + bits_for(self.seats),
+            ColumnScheme::Letters(_) => self.row_bits() + 1,
+        }
+    }
+
+    fn seat_count(&self) -> u32 {
+        u32::from(self.rows) * u32::from(self.seats)
+    }
+
+    fn has_row(&self, row: u16) -> bool {
+        row < self.rows && !self.excluded_rows.contains(&row)
+    }
+
+    fn column_label(&self, column: u16) -> String {
+        match &self.scheme {
+            ColumnScheme::Binary => column.to_string(),
+            ColumnScheme::Letters(letters) => letters[column as usize].to_string(),
+        }
+    }
+
+    // Seats of every existing row, in seat ID order
+    fn seats(&self) -> impl Iterator<Item = BoardingPass> + '_ {
+        (0..self.rows)
+            .filter(move |&row| self.has_row(row))
+            .flat_map(move |row| (0..self.seats).map(move |column| BoardingPass { row, column }))
+    }
+}
+
+impl BoardingPass {
+    fn new(row: u16, column: u16, aircraft: &Aircraft) -> Result<BoardingPass, PassError> {
+        if row >= aircraft.rows {
+            return Err(PassError::Row(row));
+        }
+        if !aircraft.has_row(row) {
+            return Err(PassError::ExcludedRow(row));
+        }
+        if column >= aircraft.seats {
+            return Err(PassError::Column(column));
+        }
+
+        Ok(BoardingPass { row, column })
+    }
+
+    fn from_seat_id(seat_id: u32, aircraft: &Aircraft) -> Result<BoardingPass, PassError> {
+        if seat_id >= aircraft.seat_count() {
+            return Err(PassError::SeatId(seat_id));
+        }
+
+        let seats = u32::from(aircraft.seats);
+        BoardingPass::new((seat_id / seats) as u16, (seat_id % seats) as u16, aircraft)
+    }
+
+    // Rows are selected by F (lower half) and B (upper half), columns by L and R
+    // or by the aircraft's seat letter
+    fn decode(code: &str, aircraft: &Aircraft) -> Result<BoardingPass, PassError> {
+        let chars: Vec<char> = code.chars().collect();
+        if chars.len() != aircraft.code_length() {
+            return Err(PassError::Length(aircraft.code_length(), chars.len()));
+        }
+
+        let row_bits = aircraft.row_bits();
+        let mut row = 0;
+        let mut column = 0;
+
+        for (position, &ch) in chars.iter().enumerate() {
+            match (position < row_bits, ch, &aircraft.scheme) {
+                (true, 'F', _) => row <<= 1,
+                (true, 'B', _) => row = (row << 1) | 1,
+                (false, 'L', ColumnScheme::Binary) => column <<= 1,
+                (false, 'R', ColumnScheme::Binary) => column = (column << 1) | 1,
+                (false, _, ColumnScheme::Letters(letters)) => {
+                    column = letters
+                        .iter()
+                        .position(|&x| x == ch)
+                        .ok_or(PassError::Character(position, ch))?
+                        as u16;
+                }
+                _ => return Err(PassError::Character(position, ch)),
+            }
+        }
+
+        BoardingPass::new(row, column, aircraft)
+    }
+
+    fn encode(&self, aircraft: &Aircraft) -> String {
+        let mut code = String::new();
+
+        for bit in (0..aircraft.row_bits()).rev() {
+            code.push(if self.row >> bit & 1 == 1 { 'B' } else { 'F' });
+        }
+        match &aircraft.scheme {
+            ColumnScheme::Binary => {
+                for bit in (0..bits_for(aircraft.seats)).rev() {
+                    code.push(if self.column >> bit & 1 == 1 {
+                        'R'
+                    } else {
+                        'L'
+                    });
+                }
+            }
+            ColumnScheme::Letters(letters) => code.push(letters[self.column as usize]),
+        }
+
+        code
+    }
+
+    fn seat_id(&self, aircraft: &Aircraft) -> u32 {
+        u32::from(self.row) * u32::from(aircraft.seats) + u32::from(self.column)
+    }
+}
 
-    let mut seat_ids: Vec<u16> = seat_id_binary_strings
+// Free seats at either end of the cabin belong to rows that are missing
+// from this flight; a free seat between two occupied ones is yours
+fn classify_seats(aircraft: &Aircraft, seat_ids: &HashSet<u32>) -> Vec<(BoardingPass, SeatStatus)> {
+    let seats: Vec<BoardingPass> = aircraft.seats().collect();
+    let occupied: Vec<bool> = seats
         .iter()
-        .map(|x| u16::from_str_radix(x, 2).unwrap())
+        .map(|x| seat_ids.contains(&x.seat_id(aircraft)))
         .collect();
-    dbg!(&seat_ids);
+    let first = occupied.iter().position(|&x| x);
+    let last = occupied.iter().rposition(|&x| x);
 
-    let max_seat_id = seat_ids.iter().max().unwrap();
-    dbg!(&max_seat_id);
+    seats
+        .into_iter()
+        .enumerate()
+        .map(|(index, seat)| {
+            let status = if occupied[index] {
+                SeatStatus::Occupied
+            } else if first.is_none_or(|first| index < first) {
+                SeatStatus::MissingFront
+            } else if last.is_none_or(|last| index > last) {
+                SeatStatus::MissingBack
+            } else if occupied[index - 1] && occupied[index + 1] {
+                SeatStatus::Yours
+            } else {
+                SeatStatus::Vacant
+            };
+            (seat, status)
+        })
+        .collect()
+}
+
+// One line per existing row: # occupied, * yours, - missing, . vacant
+fn render_map(aircraft: &Aircraft, seats: &[(BoardingPass, SeatStatus)]) -> String {
+    let label_width = aircraft.rows.saturating_sub(1).to_string().len();
+    let column_width = aircraft.column_label(aircraft.seats - 1).len();
+
+    let mut output = format!("{:>width$} ", "", width = label_width);
+    for column in 0..aircraft.seats {
+        if aircraft.aisles.contains(&column) {
+            output.push(' ');
+        }
+        output.push_str(&format!(
+            "{:>width$}",
+            aircraft.column_label(column),
+            width = column_width
+        ));
+    }
+    output.push('\n');
+
+    for row_seats in seats.chunks(aircraft.seats as usize) {
+        output.push_str(&format!(
+            "{:>width$} ",
+            row_seats[0].0.row,
+            width = label_width
+        ));
+        for (seat, status) in row_seats.iter() {
+            if aircraft.aisles.contains(&seat.column) {
+                output.push(' ');
+            }
+            let mark = match status {
+                SeatStatus::Occupied => '#',
+                SeatStatus::Yours => '*',
+                SeatStatus::MissingFront | SeatStatus::MissingBack => '-',
+                SeatStatus::Vacant => '.',
+            };
+            output.push_str(&format!("{:>width$}", mark, width = column_width));
+        }
+        output.push('\n');
+    }
+
+    output
+}
+
+fn render_svg(aircraft: &Aircraft, seats: &[(BoardingPass, SeatStatus)]) -> String {
+    let cell = 20;
+    let label = 40;
+    let aisle_count = aircraft.aisles.len() as u32;
+    let width = label + (u32::from(aircraft.seats) + aisle_count) * cell;
+    let height = cell + (seats.len() as u32 / u32::from(aircraft.seats)) * cell;
+
+    let mut output = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+         font-family=\"monospace\" font-size=\"10\">\n",
+        width, height
+    );
+
+    let x_of = |column: u16| {
+        let aisles = aircraft.aisles.iter().filter(|&&x| x <= column).count() as u32;
+        label + (u32::from(column) + aisles) * cell
+    };
+
+    for column in 0..aircraft.seats {
+        output.push_str(&format!(
+            "<text x=\"{}\" y=\"14\" text-anchor=\"middle\">{}</text>\n",
+            x_of(column) + cell / 2,
+            aircraft.column_label(column)
+        ));
+    }
+
+    for (index, row_seats) in seats.chunks(aircraft.seats as usize).enumerate() {
+        let y = cell + index as u32 * cell;
+        output.push_str(&format!(
+            "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>\n",
+            label - 6,
+            y + 14,
+            row_seats[0].0.row
+        ));
+        for (seat, status) in row_seats.iter() {
+            let fill = match status {
+                SeatStatus::Occupied => "#4a7ebb",
+                SeatStatus::Yours => "#e8a33d",
+                SeatStatus::MissingFront | SeatStatus::MissingBack => "#dddddd",
+                SeatStatus::Vacant => "#ffffff",
+            };
+            output.push_str(&format!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\" stroke=\"#555555\"/>\n",
+                x_of(seat.column) + 2,
+                y + 2,
+                cell - 4,
+                cell - 4,
+                fill
+            ));
+        }
+    }
+
+    output.push_str("</svg>\n");
+
+    output
+}
+
+fn print_vacancies(aircraft: &Aircraft, seats: &[(BoardingPass, SeatStatus)]) {
+    let describe = |status: SeatStatus| {
+        let matching: Vec<&BoardingPass> = seats
+            .iter()
+            .filter(|(_, x)| *x == status)
+            .map(|(seat, _)| seat)
+            .collect();
+        match (matching.first(), matching.last()) {
+            (Some(first), Some(last)) => format!(
+                "{} seats, from row {} seat {} to row {} seat {}",
+                matching.len(),
+                first.row,
+                aircraft.column_label(first.column),
+                last.row,
+                aircraft.column_label(last.column)
+            ),
+            _ => "none".to_string(),
+        }
+    };
+
+    println!(
+        "Missing at the front: {}",
+        describe(SeatStatus::MissingFront)
+    );
+    println!("Missing at the back: {}", describe(SeatStatus::MissingBack));
+
+    for (seat, status) in seats.iter() {
+        match status {
+            SeatStatus::Yours => println!(
+                "Your seat: row {} seat {} (seat ID {})",
+                seat.row,
+                aircraft.column_label(seat.column),
+                seat.seat_id(aircraft)
+            ),
+            SeatStatus::Vacant => println!(
+                "Other vacancy: row {} seat {} (seat ID {})",
+                seat.row,
+                aircraft.column_label(seat.column),
+                seat.seat_id(aircraft)
+            ),
+            _ => {}
+        }
+    }
+}
+
+fn encode_from_args(args: &[String], aircraft: &Aircraft) -> Result<BoardingPass, String> {
+    fn parse<T: FromStr>(text: &str) -> Result<T, String> {
+        text.parse().map_err(|_| format!("invalid number {}", text))
+    }
+
+    let pass = match args {
+        [seat_id] => BoardingPass::from_seat_id(parse(seat_id)?, aircraft),
+        [row, column] => BoardingPass::new(parse(row)?, parse(column)?, aircraft),
+        _ => return Err("usage: --encode SEAT_ID | --encode ROW COLUMN".to_string()),
+    };
+
+    pass.map_err(|e| e.to_string())
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    let aircraft = match args.iter().position(|x| x == "--profile") {
+        Some(index) => {
+            if index + 1 >= args.len() {
+                panic!("usage: --profile FILE");
+            }
+            let path = args.remove(index + 1);
+            args.remove(index);
+            let text = fs::read_to_string(&path).unwrap();
+            Aircraft::parse(&text).unwrap_or_else(|e| panic!("{}: {}", path, e))
+        }
+        None => Aircraft::new(),
+    };
 
-    seat_ids.sort_unstable();
-    dbg!(&seat_ids);
+    let mut map = false;
+    let mut svg_path = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--encode" => {
+                let rest: Vec<String> = iter.cloned().collect();
+                let pass = encode_from_args(&rest, &aircraft).unwrap_or_else(|e| panic!("{}", e));
+                println!(
+                    "{} (row {}, column {}, seat ID {})",
+                    pass.encode(&aircraft),
+                    pass.row,
+                    pass.column,
+                    pass.seat_id(&aircraft)
+                );
+                return;
+            }
+            "--map" => map = true,
+            "--svg" => svg_path = Some(iter.next().expect("usage: --svg FILE")),
+            _ => panic!("unknown argument {}", arg),
+        }
+    }
+
+    let mut input = String::new();
+    stdin().read_to_string(&mut input).unwrap();
 
-    for (a, &b) in zip(&seat_ids, &seat_ids[1..]) {
-        if a + 1 != b {
-            println!("a ({}) and b ({}) have a gap!", a, b);
+    let mut seat_ids: HashSet<u32> = HashSet::new();
+    for (index, line) in input.lines().enumerate() {
+        match BoardingPass::decode(line, &aircraft) {
+            Ok(pass) => {
+                seat_ids.insert(pass.seat_id(&aircraft));
+            }
+            Err(e) => eprintln!("line {}: {}: {}", index + 1, line, e),
         }
     }
+
+    if let Some(max_seat_id) = seat_ids.iter().max() {
+        println!("Part 1: the highest seat ID is {}", max_seat_id);
+    }
+
+    let seats = classify_seats(&aircraft, &seat_ids);
+    for (seat, _) in seats.iter().filter(|(_, x)| *x == SeatStatus::Yours) {
+        println!("Part 2: your seat ID is {}", seat.seat_id(&aircraft));
+    }
+
+    if map {
+        println!();
+        print!("{}", render_map(&aircraft, &seats));
+        println!();
+        print_vacancies(&aircraft, &seats);
+    }
+    if let Some(path) = svg_path {
+        fs::write(path, render_svg(&aircraft, &seats)).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn decode() {
+        use crate::{Aircraft, BoardingPass};
+
+        let aircraft = Aircraft::new();
+        let pass = BoardingPass::decode("BFFFBBFRRR", &aircraft).unwrap();
+        assert_eq!(
+            (pass.row, pass.column, pass.seat_id(&aircraft)),
+            (70, 7, 567)
+        );
+        let pass = BoardingPass::decode("FFFBBBFRRR", &aircraft).unwrap();
+        assert_eq!(
+            (pass.row, pass.column, pass.seat_id(&aircraft)),
+            (14, 7, 119)
+        );
+        let pass = BoardingPass::decode("BBFFBBFRLL", &aircraft).unwrap();
+        assert_eq!(
+            (pass.row, pass.column, pass.seat_id(&aircraft)),
+            (102, 4, 820)
+        );
+    }
+
+    #[test]
+    fn encode() {
+        use crate::{Aircraft, BoardingPass};
+
+        let aircraft = Aircraft::new();
+        assert_eq!(
+            BoardingPass::from_seat_id(357, &aircraft)
+                .unwrap()
+                .encode(&aircraft),
+            "FBFBBFFRLR"
+        );
+        assert_eq!(
+            BoardingPass::new(70, 7, &aircraft)
+                .unwrap()
+                .encode(&aircraft),
+            "BFFFBBFRRR"
+        );
+    }
+
+    #[test]
+    fn encode_args() {
+        use crate::{encode_from_args, Aircraft};
+
+        let aircraft = Aircraft::parse("rows 20000\nseats 8\n").unwrap();
+        let args = vec!["100000".to_string()];
+        let pass = encode_from_args(&args, &aircraft).unwrap();
+        assert_eq!((pass.row, pass.column), (12500, 0));
+        let args = vec!["12500".to_string(), "0".to_string()];
+        assert_eq!(encode_from_args(&args, &aircraft).unwrap(), pass);
+        let args = vec!["70000".to_string(), "0".to_string()];
+        assert!(encode_from_args(&args, &aircraft).is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        use crate::{Aircraft, BoardingPass, PassError};
+
+        let aircraft = Aircraft::new();
+        assert_eq!(
+            BoardingPass::decode("FBFBBFF", &aircraft),
+            Err(PassError::Length(10, 7))
+        );
+        assert_eq!(
+            BoardingPass::decode("FBFBBFFRLB", &aircraft),
+            Err(PassError::Character(9, 'B'))
+        );
+        assert_eq!(
+            BoardingPass::new(128, 0, &aircraft),
+            Err(PassError::Row(128))
+        );
+    }
+
+    #[test]
+    fn lettered_profile() {
+        use crate::{Aircraft, BoardingPass, PassError};
+
+        let aircraft = Aircraft::parse("rows 30\nletters ABC DEF\nexclude 13\n").unwrap();
+        let pass = BoardingPass::decode("FBBFFC", &aircraft).unwrap();
+        assert_eq!(
+            (pass.row, pass.column, pass.seat_id(&aircraft)),
+            (12, 2, 74)
+        );
+        assert_eq!(pass.encode(&aircraft), "FBBFFC");
+        assert_eq!(
+            BoardingPass::decode("FBBFBA", &aircraft),
+            Err(PassError::ExcludedRow(13))
+        );
+    }
+
+    #[test]
+    fn seat_classes() {
+        use crate::{classify_seats, Aircraft, SeatStatus};
+        use std::collections::HashSet;
+
+        let aircraft = Aircraft::parse("rows 4\nseats 2\n").unwrap();
+        let seat_ids: HashSet<u32> = [1, 3, 6].iter().copied().collect();
+        let statuses: Vec<SeatStatus> = classify_seats(&aircraft, &seat_ids)
+            .into_iter()
+            .map(|(_, status)| status)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                SeatStatus::MissingFront,
+                SeatStatus::Occupied,
+                SeatStatus::Yours,
+                SeatStatus::Occupied,
+                SeatStatus::Vacant,
+                SeatStatus::Vacant,
+                SeatStatus::Occupied,
+                SeatStatus::MissingBack,
+            ]
+        );
+    }
 }
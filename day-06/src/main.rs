@@ -1,51 +1,481 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::env;
 use std::io::{stdin, Read};
+use std::time::Instant;
 
-fn main() {
-    let mut input = String::new();
-    stdin().read_to_string(&mut input).unwrap();
+// How many people in a group must answer yes for a question to count
+#[derive(Debug)]
+enum Quorum {
+    Count(usize),
+    Percent(usize),
+}
 
-    let (mut groups, last_group) =
-        input
-            .lines()
-            .fold((Vec::new(), Vec::new()), |(mut acc, mut group), x| {
-                if x.is_empty() {
-                    acc.push(group);
-                    (acc, Vec::new())
+// Question labels in declaration (or first appearance) order; a label's
+// index is its bit in an answer set
+#[derive(Debug)]
+struct Alphabet {
+    labels: Vec<String>,
+    indices: HashMap<String, usize>,
+    ascii: Vec<Option<usize>>,
+    tokenised: bool,
+}
+
+// Questions one person answered yes to; alphabets beyond 64 labels fall
+// back to a multi-word bitset
+#[derive(Clone, Debug, PartialEq)]
+enum AnswerSet {
+    Narrow(u64),
+    Wide(Vec<u64>),
+}
+
+#[derive(Debug)]
+struct Group {
+    people: Vec<AnswerSet>,
+}
+
+impl Quorum {
+    // Accepts a person count, "half", "all" or a whole percentage such as "75%"
+    fn parse(text: &str) -> Result<Quorum, String> {
+        match text {
+            "half" => Ok(Quorum::Percent(50)),
+            "all" => Ok(Quorum::Percent(100)),
+            _ => {
+                if let Some(percentage) = text.strip_suffix('%') {
+                    let percentage: usize = percentage
+                        .parse()
+                        .map_err(|_| format!("invalid percentage {}", text))?;
+                    if percentage > 100 {
+                        return Err(format!("percentage {} is out of range", text));
+                    }
+                    Ok(Quorum::Percent(percentage))
                 } else {
-                    group.push(x);
-                    (acc, group)
+                    text.parse()
+                        .map(Quorum::Count)
+                        .map_err(|_| format!("invalid quorum {}", text))
                 }
+            }
+        }
+    }
+
+    fn threshold(&self, group_size: usize) -> usize {
+        match self {
+            Quorum::Count(count) => *count,
+            // Rounded up in integer arithmetic so 28% of 25 is exactly 7
+            Quorum::Percent(percentage) => (group_size * percentage).div_ceil(100),
+        }
+    }
+}
+
+impl Alphabet {
+    // Multi-character labels such as numbered questions are written as
+    // whitespace-separated tokens; otherwise every character is a label
+    fn new(labels: Vec<String>, tokenised: bool) -> Result<Alphabet, String> {
+        let mut indices = HashMap::new();
+        let mut ascii = vec![None; 128];
+
+        for (index, label) in labels.iter().enumerate() {
+            if indices.insert(label.clone(), index).is_some() {
+                return Err(format!("question {} is declared twice", label));
+            }
+            if label.len() == 1 && label.is_ascii() {
+                ascii[label.as_bytes()[0] as usize] = Some(index);
+            }
+        }
+
+        Ok(Alphabet {
+            labels,
+            indices,
+            ascii,
+            tokenised,
+        })
+    }
+
+    fn words(&self) -> usize {
+        self.labels.len().div_ceil(64).max(1)
+    }
+
+    fn empty_set(&self) -> AnswerSet {
+        match self.words() {
+            1 => AnswerSet::Narrow(0),
+            words => AnswerSet::Wide(vec![0; words]),
+        }
+    }
+
+    fn answer_set(&self, line: &str) -> Result<AnswerSet, String> {
+        let mut set = self.empty_set();
+
+        if self.tokenised {
+            for label in line.split_whitespace() {
+                let index = self
+                    .indices
+                    .get(label)
+                    .ok_or_else(|| format!("unknown question {}", label))?;
+                set.insert(*index);
+            }
+        } else {
+            let mut buffer = [0; 4];
+            for ch in line.chars().filter(|ch| !ch.is_whitespace()) {
+                let index = if ch.is_ascii() {
+                    self.ascii[ch as usize]
+                } else {
+                    self.indices.get(&*ch.encode_utf8(&mut buffer)).copied()
+                };
+                set.insert(index.ok_or_else(|| format!("unknown question {}", ch))?);
+            }
+        }
+
+        Ok(set)
+    }
+}
+
+impl AnswerSet {
+    fn insert(&mut self, index: usize) {
+        match self {
+            AnswerSet::Narrow(bits) => *bits |= 1 << index,
+            AnswerSet::Wide(words) => words[index / 64] |= 1 << (index % 64),
+        }
+    }
+
+    fn contains(&self, index: usize) -> bool {
+        match self {
+            AnswerSet::Narrow(bits) => bits >> index & 1 == 1,
+            AnswerSet::Wide(words) => words[index / 64] >> (index % 64) & 1 == 1,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            AnswerSet::Narrow(bits) => bits.count_ones() as usize,
+            AnswerSet::Wide(words) => words.iter().map(|x| x.count_ones() as usize).sum(),
+        }
+    }
+
+    fn combine(&self, other: &AnswerSet, op: fn(u64, u64) -> u64) -> AnswerSet {
+        match (self, other) {
+            (AnswerSet::Narrow(a), AnswerSet::Narrow(b)) => AnswerSet::Narrow(op(*a, *b)),
+            (AnswerSet::Wide(a), AnswerSet::Wide(b)) => {
+                AnswerSet::Wide(a.iter().zip(b.iter()).map(|(x, y)| op(*x, *y)).collect())
+            }
+            _ => panic!("answer sets from different alphabets"),
+        }
+    }
+}
+
+impl Group {
+    fn fold(&self, op: fn(u64, u64) -> u64) -> Option<AnswerSet> {
+        let mut people = self.people.iter();
+        let first = people.next()?.clone();
+
+        Some(people.fold(first, |acc, x| acc.combine(x, op)))
+    }
+
+    fn anyone(&self) -> usize {
+        self.fold(|a, b| a | b).map_or(0, |x| x.len())
+    }
+
+    fn everyone(&self) -> usize {
+        self.fold(|a, b| a & b).map_or(0, |x| x.len())
+    }
+
+    // Questions in the symmetric difference of everyone's answers
+    fn symmetric_difference(&self) -> usize {
+        self.fold(|a, b| a ^ b).map_or(0, |x| x.len())
+    }
+
+    // Number of people who answered yes to each question
+    fn tally(&self, alphabet: &Alphabet) -> Vec<usize> {
+        (0..alphabet.labels.len())
+            .map(|index| self.people.iter().filter(|x| x.contains(index)).count())
+            .collect()
+    }
+
+    fn at_least(&self, alphabet: &Alphabet, k: usize) -> usize {
+        match k {
+            0 | 1 => self.anyone(),
+            k if k == self.people.len() => self.everyone(),
+            k => self.tally(alphabet).iter().filter(|&&x| x >= k).count(),
+        }
+    }
+
+    fn exactly(&self, alphabet: &Alphabet, k: usize) -> usize {
+        self.tally(alphabet).iter().filter(|&&x| x == k).count()
+    }
+}
+
+fn split_groups<'a>(lines: &[&'a str]) -> Vec<Vec<(usize, &'a str)>> {
+    let mut groups = vec![Vec::new()];
+
+    for (index, line) in lines.iter().enumerate() {
+        if line.is_empty() {
+            groups.push(Vec::new());
+        } else {
+            groups.last_mut().unwrap().push((index, *line));
+        }
+    }
+
+    groups
+}
+
+// An optional first line "questions: a b c 1 2 12" declares the alphabet;
+// without it the alphabet is inferred from the answers
+fn parse_forms(input: &str) -> Result<(Alphabet, Vec<Group>), String> {
+    let mut lines: Vec<&str> = input.lines().collect();
+    let mut first_line = 1;
+
+    let declared = match lines.first().and_then(|x| x.strip_prefix("questions:")) {
+        Some(header) => {
+            let labels: Vec<String> = header.split_whitespace().map(String::from).collect();
+            lines.remove(0);
+            first_line += 1;
+            Some(labels)
+        }
+        None => None,
+    };
+
+    let alphabet = match declared {
+        Some(labels) => {
+            let tokenised = labels.iter().any(|x| x.chars().count() > 1);
+            Alphabet::new(labels, tokenised)?
+        }
+        None => {
+            let tokenised = lines.iter().any(|x| x.trim().contains(char::is_whitespace));
+            let mut labels: Vec<String> = if tokenised {
+                let seen: HashSet<&str> = lines.iter().flat_map(|x| x.split_whitespace()).collect();
+                seen.into_iter().map(String::from).collect()
+            } else {
+                let seen: HashSet<char> = lines
+                    .iter()
+                    .flat_map(|x| x.chars())
+                    .filter(|ch| !ch.is_whitespace())
+                    .collect();
+                seen.into_iter().map(String::from).collect()
+            };
+            labels.sort_unstable_by(|a, b| match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(x), Ok(y)) => x.cmp(&y),
+                _ => a.cmp(b),
             });
-    groups.push(last_group);
+            Alphabet::new(labels, tokenised)?
+        }
+    };
 
-    let mut sum = 0;
-    for group in groups.iter() {
-        let mut answers: Vec<char> = group.iter().map(|g| g.chars()).flatten().collect();
-        answers.sort_unstable();
-        answers.dedup();
-        sum += answers.len();
+    let mut groups = Vec::new();
+    for group in split_groups(&lines) {
+        let mut people = Vec::new();
+        for (index, line) in group.iter() {
+            let set = alphabet
+                .answer_set(line)
+                .map_err(|e| format!("line {}: {}", index + first_line, e))?;
+            people.push(set);
+        }
+        groups.push(Group { people });
     }
 
-    println!("Part 1: the sum of the counts is {}", sum);
+    Ok((alphabet, groups))
+}
+
+fn print_histogram(alphabet: &Alphabet, groups: &[Group]) {
+    let mut people = vec![0; alphabet.labels.len()];
+    let mut anyone = vec![0; alphabet.labels.len()];
+    let mut everyone = vec![0; alphabet.labels.len()];
 
-    let mut sum = 0;
     for group in groups.iter() {
-        let person_hss: Vec<HashSet<char>> = group
-            .iter()
+        for (index, &count) in group.tally(alphabet).iter().enumerate() {
+            people[index] += count;
+            if count > 0 {
+                anyone[index] += 1;
+            }
+            if count > 0 && count == group.people.len() {
+                everyone[index] += 1;
+            }
+        }
+    }
+
+    let widest = people.iter().max().copied().unwrap_or(0);
+    println!("question   people   anyone   everyone");
+    for (index, label) in alphabet.labels.iter().enumerate() {
+        let bar = "#".repeat((people[index] * 40).div_ceil(widest.max(1)));
+        println!(
+            "{:>8} {:>8} {:>8} {:>10}   {}",
+            label, people[index], anyone[index], everyone[index], bar
+        );
+    }
+}
+
+// The original representation: one HashSet per person, with the intersection
+// seeded from the group's first person. Answers are split into labels the same
+// way as the alphabet does, so both representations count the same questions.
+fn hashset_counts(alphabet: &Alphabet, input: &str) -> (usize, usize) {
+    let mut anyone = 0;
+    let mut everyone = 0;
+
+    for group in input.split("\n\n") {
+        let people: Vec<HashSet<&str>> = group
+            .lines()
+            .filter(|x| !x.is_empty() && !x.starts_with("questions:"))
             .map(|x| {
-                let hs = x.chars().collect();
-                hs
+                if alphabet.tokenised {
+                    x.split_whitespace().collect()
+                } else {
+                    x.char_indices()
+                        .filter(|(_, ch)| !ch.is_whitespace())
+                        .map(|(index, ch)| &x[index..index + ch.len_utf8()])
+                        .collect()
+                }
             })
             .collect();
+        if people.is_empty() {
+            continue;
+        }
 
-        let mut common_hs: HashSet<char> = ('a'..='z').collect();
-        for person_hs in person_hss.iter() {
-            common_hs.retain(|x| person_hs.contains(&x));
+        let mut any_hs: HashSet<&str> = HashSet::new();
+        let mut common_hs = people[0].clone();
+        for person_hs in people.iter() {
+            any_hs.extend(person_hs.iter());
+            common_hs.retain(|x| person_hs.contains(x));
         }
+        anyone += any_hs.len();
+        everyone += common_hs.len();
+    }
+
+    (anyone, everyone)
+}
+
+fn run_benchmark(input: &str, rounds: u32) {
+    let (alphabet, _) = parse_forms(input).unwrap_or_else(|e| panic!("{}", e));
 
-        sum += common_hs.len();
+    let start = Instant::now();
+    let mut hashset_result = (0, 0);
+    for _ in 0..rounds {
+        hashset_result = hashset_counts(&alphabet, input);
     }
+    let hashset_elapsed = start.elapsed();
 
-    println!("Part 2: the sum of the counts is {}", sum);
+    let start = Instant::now();
+    let mut bitset_result = (0, 0);
+    for _ in 0..rounds {
+        let (_, groups) = parse_forms(input).unwrap_or_else(|e| panic!("{}", e));
+        bitset_result = (
+            groups.iter().map(|x| x.anyone()).sum(),
+            groups.iter().map(|x| x.everyone()).sum(),
+        );
+    }
+    let bitset_elapsed = start.elapsed();
+
+    println!(
+        "HashSet: {:?} per round, anyone {} everyone {}",
+        hashset_elapsed / rounds,
+        hashset_result.0,
+        hashset_result.1
+    );
+    println!(
+        "Bitset:  {:?} per round, anyone {} everyone {}",
+        bitset_elapsed / rounds,
+        bitset_result.0,
+        bitset_result.1
+    );
+    assert_eq!(
+        hashset_result, bitset_result,
+        "the HashSet and bitset counts differ"
+    );
+    println!(
+        "Speedup: {:.2}x",
+        hashset_elapsed.as_secs_f64() / bitset_elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let mut input = String::new();
+    stdin().read_to_string(&mut input).unwrap();
+
+    if let ["--benchmark", rounds @ ..] =
+        &args.iter().map(|x| x.as_str()).collect::<Vec<&str>>()[..]
+    {
+        let rounds = rounds.first().map_or(10, |x| x.parse().unwrap());
+        run_benchmark(&input, rounds);
+        return;
+    }
+
+    let (alphabet, groups) = parse_forms(&input).unwrap_or_else(|e| panic!("{}", e));
+
+    match args.iter().map(|x| x.as_str()).collect::<Vec<&str>>()[..] {
+        [] => {
+            let sum: usize = groups.iter().map(|x| x.anyone()).sum();
+            println!("Part 1: the sum of the counts is {}", sum);
+
+            let sum: usize = groups.iter().map(|x| x.everyone()).sum();
+            println!("Part 2: the sum of the counts is {}", sum);
+        }
+        ["--at-least", quorum] => {
+            let quorum = Quorum::parse(quorum).unwrap_or_else(|e| panic!("{}", e));
+            let sum: usize = groups
+                .iter()
+                .map(|x| x.at_least(&alphabet, quorum.threshold(x.people.len())))
+                .sum();
+            println!("The sum of the quorum counts is {}", sum);
+        }
+        ["--exactly-one"] => {
+            let sum: usize = groups.iter().map(|x| x.exactly(&alphabet, 1)).sum();
+            println!("The sum of the exactly-one counts is {}", sum);
+        }
+        ["--symmetric-difference"] => {
+            let sum: usize = groups.iter().map(|x| x.symmetric_difference()).sum();
+            println!("The sum of the symmetric difference counts is {}", sum);
+        }
+        ["--histogram"] => print_histogram(&alphabet, &groups),
+        _ => panic!(
+            "usage: [--at-least K|half|all|P% | --exactly-one | --symmetric-difference | --histogram | --benchmark [ROUNDS]]"
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn wide_alphabet() {
+        use crate::parse_forms;
+
+        let mut input = String::from("questions:");
+        for question in 1..=100 {
+            input.push_str(&format!(" {}", question));
+        }
+        input.push_str("\n1 70 100\n70 100\n\n99\n");
+
+        let (alphabet, groups) = parse_forms(&input).unwrap();
+        assert_eq!(alphabet.labels.len(), 100);
+        assert_eq!(groups[0].anyone(), 3);
+        assert_eq!(groups[0].everyone(), 2);
+        assert_eq!(groups[1].everyone(), 1);
+    }
+
+    #[test]
+    fn quorum() {
+        use crate::Quorum;
+
+        assert_eq!(Quorum::parse("half").unwrap().threshold(4), 2);
+        assert_eq!(Quorum::parse("half").unwrap().threshold(3), 2);
+        assert_eq!(Quorum::parse("75%").unwrap().threshold(4), 3);
+        assert_eq!(Quorum::parse("all").unwrap().threshold(5), 5);
+        assert_eq!(Quorum::parse("28%").unwrap().threshold(25), 7);
+        assert_eq!(Quorum::parse("0%").unwrap().threshold(25), 0);
+        assert_eq!(Quorum::parse("2").unwrap().threshold(9), 2);
+        assert!(Quorum::parse("150%").is_err());
+        assert!(Quorum::parse("12.5%").is_err());
+        assert!(Quorum::parse("many").is_err());
+    }
+
+    #[test]
+    fn exactly_one_and_symmetric_difference() {
+        use crate::parse_forms;
+
+        let input = "abc\n\na\nb\nc\n\nab\nac\n\na\na\na\na\n\nb\n\nab\na\na\n";
+        let (alphabet, groups) = parse_forms(input).unwrap();
+
+        let exactly_one: Vec<usize> = groups.iter().map(|x| x.exactly(&alphabet, 1)).collect();
+        let symmetric: Vec<usize> = groups.iter().map(|x| x.symmetric_difference()).collect();
+        assert_eq!(exactly_one, vec![3, 3, 2, 0, 1, 1]);
+        assert_eq!(symmetric, vec![3, 3, 2, 0, 1, 2]);
+        assert_eq!(groups[2].at_least(&alphabet, 2), 1);
+    }
 }
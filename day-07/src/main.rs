@@ -3,7 +3,9 @@ extern crate pest;
 extern crate pest_derive;
 
 use pest::Parser;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::fs;
 use std::io::{stdin, Read};
 
 #[derive(Parser)]
@@ -27,85 +29,400 @@ impl State {
         }
     }
 
-    fn parse_line(&mut self, line: &str) {
-        let pairs =
-            RegulationParser::parse(Rule::regulation, line).unwrap_or_else(|e| panic!("{}", e));
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let pairs = RegulationParser::parse(Rule::line, line)
+            .map_err(|_| format!("unrecognized rule \"{}\"", line))?;
 
         let mut outer_bag_color: String = String::new();
-        let mut inner_multiple_bags_quantity = 0;
+        let mut inner_bags_quantity = 0;
 
         for pair in pairs {
             let rule = pair.as_rule();
-            let text = pair.clone().as_span().as_str().to_string();
+            let text = pair.as_span().as_str().to_string();
 
             match rule {
                 Rule::outer_bag_color => {
+                    self.containments.entry(text.clone()).or_default();
                     outer_bag_color.push_str(&text);
                 }
-                Rule::inner_single_bag_color => {
-                    let vec = self
-                        .containables
-                        .entry(text.clone())
-                        .or_insert_with(Vec::new);
-                    vec.push(outer_bag_color.clone());
-                    let vec = self
-                        .containments
-                        .entry(outer_bag_color.clone())
-                        .or_insert_with(Vec::new);
-                    vec.push((1, text.clone()));
-                }
-                Rule::inner_multiple_bags_quantity => {
-                    inner_multiple_bags_quantity = text.parse::<u32>().unwrap();
+                Rule::inner_bags_quantity => {
+                    inner_bags_quantity = text
+                        .parse::<u32>()
+                        .map_err(|_| format!("quantity {} is too large", text))?;
                 }
-                Rule::inner_multiple_bags_color => {
-                    let vec = self
-                        .containables
-                        .entry(text.clone())
-                        .or_insert_with(Vec::new);
+                Rule::inner_bags_color => {
+                    let vec = self.containables.entry(text.clone()).or_default();
                     vec.push(outer_bag_color.clone());
                     let vec = self
                         .containments
                         .entry(outer_bag_color.clone())
-                        .or_insert_with(Vec::new);
-                    vec.push((inner_multiple_bags_quantity, text.clone()));
+                        .or_default();
+                    vec.push((inner_bags_quantity, text));
                 }
+                Rule::EOI => {}
                 _ => {
-                    println!("unrecognized rule {}", &text);
+                    return Err(format!("unexpected {:?} in \"{}\"", rule, line));
                 }
             }
         }
+
+        Ok(())
     }
 
-    fn count_containable(&mut self, target_color: &str) -> usize {
+    // Colors that can eventually contain the target color
+    fn ancestors(&self, target_color: &str) -> HashSet<&str> {
         let mut candidates = vec![target_color];
         let mut containable_hs: HashSet<&str> = HashSet::new();
 
         while let Some(color) = candidates.pop() {
             if let Some(vec) = self.containables.get(color) {
                 for containable in vec.iter() {
-                    containable_hs.insert(&containable);
-                    candidates.push(&containable);
+                    if containable_hs.insert(containable) {
+                        candidates.push(containable);
+                    }
+                }
+            }
+        }
+
+        containable_hs
+    }
+
+    // Colors that the target color eventually contains
+    fn descendants(&self, target_color: &str) -> HashSet<&str> {
+        let mut candidates = vec![target_color];
+        let mut contained_hs: HashSet<&str> = HashSet::new();
+
+        while let Some(color) = candidates.pop() {
+            if let Some(vec) = self.containments.get(color) {
+                for (_, contained) in vec.iter() {
+                    if contained_hs.insert(contained) {
+                        candidates.push(contained);
+                    }
                 }
             }
         }
 
-        containable_hs.len()
+        contained_hs
+    }
+
+    fn count_containable(&self, target_color: &str) -> usize {
+        self.ancestors(target_color).len()
     }
 
-    fn calculate_containment(&self, target_color: &str) -> u32 {
-        let mut count = 1;
+    // Renders the containment graph, optionally restricted to a set of colors
+    fn to_dot(&self, colors: Option<&HashSet<&str>>, highlight: Option<&str>) -> String {
+        let included = |color: &str| colors.is_none_or(|x| x.contains(color));
+
+        let mut nodes: Vec<&str> = self
+            .containments
+            .iter()
+            .flat_map(|(outer, vec)| {
+                std::iter::once(outer.as_str()).chain(vec.iter().map(|(_, x)| x.as_str()))
+            })
+            .filter(|x| included(x))
+            .collect();
+        nodes.sort_unstable();
+        nodes.dedup();
 
-        if let Some(vec) = self.containments.get(target_color) {
-            for (quantity, color) in vec.iter() {
-                count += quantity * self.calculate_containment(color);
+        let mut output = String::from("digraph bags {\n    rankdir=LR;\n");
+        for node in nodes.iter() {
+            if Some(*node) == highlight {
+                output.push_str(&format!(
+                    "    \"{}\" [style=filled, fillcolor=gold, penwidth=2];\n",
+                    node
+                ));
+            } else {
+                output.push_str(&format!("    \"{}\";\n", node));
+            }
+        }
+
+        let mut outers: Vec<&String> = self.containments.keys().collect();
+        outers.sort_unstable();
+        for outer in outers {
+            if !included(outer) {
+                continue;
+            }
+            for (quantity, inner) in self.containments[outer].iter() {
+                if included(inner) {
+                    output.push_str(&format!(
+                        "    \"{}\" -> \"{}\" [label=\"{}\"];\n",
+                        outer, inner, quantity
+                    ));
+                }
             }
         }
+        output.push_str("}\n");
 
-        count
+        output
+    }
+
+    // Colors ordered so that every color comes after everything it contains,
+    // or the first cycle found as a list of colors
+    fn containment_order(&self) -> Result<Vec<&str>, Vec<&str>> {
+        let mut colors: Vec<&str> = self.containments.keys().map(|x| x.as_str()).collect();
+        colors.sort_unstable();
+
+        let mut order = Vec::new();
+        let mut done: HashSet<&str> = HashSet::new();
+        let mut on_path: HashSet<&str> = HashSet::new();
+
+        for &start in colors.iter() {
+            if done.contains(start) {
+                continue;
+            }
+
+            // Walk depth first without recursion; each frame holds a color
+            // and the index of the next content to visit
+            let mut path: Vec<(&str, usize)> = vec![(start, 0)];
+            on_path.insert(start);
+
+            while let Some((color, index)) = path.pop() {
+                let contents = self.containments.get(color);
+                match contents.and_then(|x| x.get(index)) {
+                    Some((_, inner)) => {
+                        path.push((color, index + 1));
+                        let inner = inner.as_str();
+                        if on_path.contains(inner) {
+                            let mut cycle: Vec<&str> = path
+                                .iter()
+                                .map(|(x, _)| *x)
+                                .skip_while(|x| *x != inner)
+                                .collect();
+                            cycle.push(inner);
+                            return Err(cycle);
+                        }
+                        if !done.contains(inner) {
+                            on_path.insert(inner);
+                            path.push((inner, 0));
+                        }
+                    }
+                    None => {
+                        on_path.remove(color);
+                        done.insert(color);
+                        order.push(color);
+                    }
+                }
+            }
+        }
+
+        Ok(order)
+    }
+
+    fn checked_order(&self) -> Result<Vec<&str>, String> {
+        self.containment_order()
+            .map_err(|cycle| format!("bag rules contain a cycle: {}", cycle.join(" -> ")))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        self.checked_order().map(|_| ())
+    }
+
+    // Counts the target bag itself along with everything inside it
+    fn calculate_containment(&self, target_color: &str) -> Result<u128, String> {
+        let totals = self.containment_totals()?;
+
+        Ok(totals.get(target_color).copied().unwrap_or(1))
+    }
+
+    // Each color's bag count, including the bag itself
+    fn containment_totals(&self) -> Result<HashMap<&str, u128>, String> {
+        let order = self.checked_order()?;
+        let mut totals: HashMap<&str, u128> = HashMap::new();
+
+        for color in order {
+            let mut count: u128 = 1;
+            for (quantity, inner) in self.containments.get(color).into_iter().flatten() {
+                let inner_total = totals.get(inner.as_str()).copied().unwrap_or(1);
+                count = u128::from(*quantity)
+                    .checked_mul(inner_total)
+                    .and_then(|x| x.checked_add(count))
+                    .ok_or_else(|| format!("bag count inside {} overflows", color))?;
+            }
+            totals.insert(color, count);
+        }
+
+        Ok(totals)
+    }
+
+    // Colors whose rule says they contain no other bags
+    fn leaves(&self) -> Vec<&str> {
+        let mut leaves: Vec<&str> = self
+            .containments
+            .iter()
+            .filter(|(_, vec)| vec.is_empty())
+            .map(|(color, _)| color.as_str())
+            .collect();
+        leaves.sort_unstable();
+
+        leaves
+    }
+
+    // Per color: how deeply its contents nest, how many distinct colors it
+    // transitively contains, and how many bags it holds in total
+    fn hierarchy_stats(&self) -> Result<Vec<(&str, usize, usize, u128)>, String> {
+        let order = self.checked_order()?;
+        let totals = self.containment_totals()?;
+        let mut depths: HashMap<&str, usize> = HashMap::new();
+
+        for &color in order.iter() {
+            let depth = self
+                .containments
+                .get(color)
+                .into_iter()
+                .flatten()
+                .map(|(_, inner)| depths.get(inner.as_str()).copied().unwrap_or(0) + 1)
+                .max()
+                .unwrap_or(0);
+            depths.insert(color, depth);
+        }
+
+        let mut stats: Vec<(&str, usize, usize, u128)> = order
+            .iter()
+            .map(|&color| {
+                (
+                    color,
+                    depths[color],
+                    self.descendants(color).len(),
+                    totals[color] - 1,
+                )
+            })
+            .collect();
+        stats.sort_unstable_by(|a, b| {
+            b.1.cmp(&a.1)
+                .then_with(|| b.2.cmp(&a.2))
+                .then_with(|| b.3.cmp(&a.3))
+                .then_with(|| a.0.cmp(b.0))
+        });
+
+        Ok(stats)
+    }
+
+    // Total number of bags of each color inside one bag of the target color
+    fn required_contents<'a>(
+        &'a self,
+        target_color: &'a str,
+    ) -> Result<Vec<(&'a str, u128)>, String> {
+        let order = self.checked_order()?;
+        let mut needed: HashMap<&str, u128> = HashMap::new();
+        needed.insert(target_color, 1);
+
+        // Outer colors come last in the order, so walk it backwards
+        for &color in order.iter().rev() {
+            let count = match needed.get(color) {
+                Some(&count) => count,
+                None => continue,
+            };
+            for (quantity, inner) in self.containments.get(color).into_iter().flatten() {
+                let entry = needed.entry(inner.as_str()).or_insert(0);
+                *entry = u128::from(*quantity)
+                    .checked_mul(count)
+                    .and_then(|x| x.checked_add(*entry))
+                    .ok_or_else(|| format!("number of {} bags overflows", inner))?;
+            }
+        }
+
+        needed.remove(target_color);
+        let mut contents: Vec<(&str, u128)> = needed.into_iter().collect();
+        contents.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+
+        Ok(contents)
+    }
+
+    // Fewest nesting steps from one color down to another, as the colors
+    // along the way and the quantity of each inside the previous one; a bag
+    // never contains its own color, since validate() rejects cycles
+    fn shortest_chain<'a>(
+        &'a self,
+        from_color: &'a str,
+        to_color: &'a str,
+    ) -> Option<Vec<(u32, &'a str)>> {
+        if from_color == to_color {
+            return None;
+        }
+
+        let mut previous: HashMap<&str, (u32, &str)> = HashMap::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(from_color);
+
+        while let Some(color) = queue.pop_front() {
+            if color == to_color {
+                let mut chain = vec![(1, to_color)];
+                let mut current = to_color;
+                while current != from_color {
+                    let (quantity, outer) = previous[current];
+                    chain.last_mut().unwrap().0 = quantity;
+                    chain.push((1, outer));
+                    current = outer;
+                }
+                chain.reverse();
+                return Some(chain);
+            }
+
+            for (quantity, inner) in self.containments.get(color).into_iter().flatten() {
+                let inner = inner.as_str();
+                if inner != from_color && !previous.contains_key(inner) {
+                    previous.insert(inner, (*quantity, color));
+                    queue.push_back(inner);
+                }
+            }
+        }
+
+        None
     }
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut dot_path = None;
+    let mut ancestors_of = None;
+    let mut descendants_of = None;
+    let mut highlight = None;
+    let mut target = "shiny gold";
+    let mut containers_of = None;
+    let mut contents_of = None;
+    let mut chain = None;
+    let mut stats = false;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .unwrap_or_else(|| panic!("{} requires a value", arg))
+                .as_str()
+        };
+        match arg.as_str() {
+            "--dot" => dot_path = Some(value()),
+            "--ancestors" => ancestors_of = Some(value()),
+            "--descendants" => descendants_of = Some(value()),
+            "--highlight" => highlight = Some(value()),
+            "--target" => target = value(),
+            "--containers" => containers_of = Some(value()),
+            "--contents" => contents_of = Some(value()),
+            "--chain" => {
+                let from_color = value();
+                chain = Some((from_color, value()));
+            }
+            "--stats" => stats = true,
+            _ => panic!("unknown argument {}", arg),
+        }
+    }
+
+    let queries = [
+        dot_path.is_some(),
+        stats,
+        containers_of.is_some(),
+        contents_of.is_some(),
+        chain.is_some(),
+    ];
+    if queries.iter().filter(|&&x| x).count() > 1 {
+        panic!("--dot, --stats, --containers, --contents and --chain are exclusive");
+    }
+    if dot_path.is_none()
+        && (ancestors_of.is_some() || descendants_of.is_some() || highlight.is_some())
+    {
+        panic!("--ancestors, --descendants and --highlight require --dot");
+    }
+
     let mut input = String::new();
     stdin().read_to_string(&mut input).unwrap();
 
@@ -113,17 +430,231 @@ fn main() {
 
     let mut state = State::new();
 
-    for line in lines.iter() {
-        state.parse_line(&line);
+    for (index, line) in lines.iter().enumerate() {
+        if let Err(e) = state.parse_line(line) {
+            eprintln!("line {}: {}", index + 1, e);
+        }
+    }
+
+    state.validate().unwrap_or_else(|e| panic!("{}", e));
+
+    if let Some(path) = dot_path {
+        let (colors, target) = match (ancestors_of, descendants_of) {
+            (Some(_), Some(_)) => panic!("--ancestors and --descendants are exclusive"),
+            (Some(color), None) => (Some(state.ancestors(color)), Some(color)),
+            (None, Some(color)) => (Some(state.descendants(color)), Some(color)),
+            (None, None) => (None, None),
+        };
+        let colors = colors.map(|mut x| {
+            x.extend(target);
+            x
+        });
+        fs::write(path, state.to_dot(colors.as_ref(), highlight.or(target))).unwrap();
+        return;
+    }
+
+    if stats {
+        let leaves = state.leaves();
+        println!("{} leaf colors contain no other bags:", leaves.len());
+        for leaf in leaves {
+            println!("  {}", leaf);
+        }
+
+        println!();
+        println!(
+            "{:>5} {:>8} {:>20}  color",
+            "depth", "distinct", "bags inside"
+        );
+        for (color, depth, distinct, total) in
+            state.hierarchy_stats().unwrap_or_else(|e| panic!("{}", e))
+        {
+            println!("{:>5} {:>8} {:>20}  {}", depth, distinct, total, color);
+        }
+        return;
+    }
+
+    if let Some(color) = containers_of {
+        let mut containers: Vec<&str> = state.ancestors(color).into_iter().collect();
+        containers.sort_unstable();
+        println!("{} bag colors can contain {}:", containers.len(), color);
+        for container in containers {
+            println!("  {}", container);
+        }
+        return;
+    }
+
+    if let Some(color) = contents_of {
+        let contents = state
+            .required_contents(color)
+            .unwrap_or_else(|e| panic!("{}", e));
+        let total: u128 = contents.iter().map(|(_, count)| count).sum();
+        println!("One {} bag must contain {} bags:", color, total);
+        for (inner, count) in contents {
+            println!("  {} {}", count, inner);
+        }
+        return;
+    }
+
+    if let Some((from_color, to_color)) = chain {
+        match state.shortest_chain(from_color, to_color) {
+            Some(chain) => {
+                let steps: Vec<String> = chain
+                    .iter()
+                    .enumerate()
+                    .map(|(index, (quantity, color))| {
+                        if index == 0 {
+                            color.to_string()
+                        } else {
+                            format!("{} {}", quantity, color)
+                        }
+                    })
+                    .collect();
+                println!("{}", steps.join(" -> "));
+            }
+            None => println!("{} bags cannot contain {} bags", from_color, to_color),
+        }
+        return;
     }
 
     println!(
-        "Part 1: {} bag colors can contain shiny gold",
-        state.count_containable("shiny gold")
+        "Part 1: {} bag colors can contain {}",
+        state.count_containable(target),
+        target
     );
 
     println!(
-        "Part 2: {} bags are required inside one shiny gold bag",
-        state.calculate_containment("shiny gold") - 1
+        "Part 2: {} bags are required inside one {} bag",
+        state
+            .calculate_containment(target)
+            .unwrap_or_else(|e| panic!("{}", e))
+            - 1,
+        target
     );
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn cycle() {
+        use crate::State;
+
+        let mut state = State::new();
+        state
+            .parse_line("light red bags contain 2 dark blue bags.")
+            .unwrap();
+        state
+            .parse_line("dark blue bags contain 1 shiny gold bag.")
+            .unwrap();
+        state
+            .parse_line("shiny gold bags contain 3 light red bags.")
+            .unwrap();
+        assert_eq!(
+            state.validate(),
+            Err(
+                "bag rules contain a cycle: dark blue -> shiny gold -> light red -> dark blue"
+                    .to_string()
+            )
+        );
+        assert!(state.calculate_containment("shiny gold").is_err());
+    }
+
+    #[test]
+    fn rule_syntax() {
+        use crate::State;
+
+        let mut state = State::new();
+        state
+            .parse_line("pale bags contain 12 very dark navy blue bag, 1 red bags.")
+            .unwrap();
+        state
+            .parse_line("very dark navy blue bags contain no other bags.")
+            .unwrap();
+        assert_eq!(
+            state.containments["pale"],
+            vec![
+                (12, "very dark navy blue".to_string()),
+                (1, "red".to_string())
+            ]
+        );
+        assert!(state.parse_line("pale bags contain some bags.").is_err());
+        assert!(state
+            .parse_line("red bags contain no other bags. red bags contain no other bags.")
+            .is_err());
+    }
+
+    fn sample() -> crate::State {
+        let mut state = crate::State::new();
+        for line in [
+            "light red bags contain 1 bright white bag, 2 muted yellow bags.",
+            "dark orange bags contain 3 bright white bags, 4 muted yellow bags.",
+            "bright white bags contain 1 shiny gold bag.",
+            "muted yellow bags contain 2 shiny gold bags, 9 faded blue bags.",
+            "shiny gold bags contain 1 dark olive bag, 2 vibrant plum bags.",
+            "dark olive bags contain 3 faded blue bags, 4 dotted black bags.",
+            "vibrant plum bags contain 5 faded blue bags, 6 dotted black bags.",
+            "faded blue bags contain no other bags.",
+            "dotted black bags contain no other bags.",
+        ] {
+            state.parse_line(line).unwrap();
+        }
+
+        state
+    }
+
+    #[test]
+    fn contents_and_chains() {
+        let state = sample();
+
+        let contents = state.required_contents("shiny gold").unwrap();
+        assert_eq!(
+            contents,
+            vec![
+                ("dotted black", 16),
+                ("faded blue", 13),
+                ("vibrant plum", 2),
+                ("dark olive", 1)
+            ]
+        );
+        assert_eq!(contents.iter().map(|x| x.1).sum::<u128>(), 32);
+
+        assert_eq!(
+            state.shortest_chain("light red", "faded blue"),
+            Some(vec![
+                (1, "light red"),
+                (2, "muted yellow"),
+                (9, "faded blue")
+            ])
+        );
+        assert_eq!(state.shortest_chain("faded blue", "shiny gold"), None);
+        assert_eq!(state.shortest_chain("shiny gold", "shiny gold"), None);
+    }
+
+    #[test]
+    fn dot_filtering() {
+        let state = sample();
+
+        let mut ancestors = state.ancestors("shiny gold");
+        ancestors.insert("shiny gold");
+        let dot = state.to_dot(Some(&ancestors), Some("shiny gold"));
+        assert!(dot.contains("\"light red\" -> \"muted yellow\" [label=\"2\"];"));
+        assert!(dot.contains("\"shiny gold\" [style=filled"));
+        assert!(!dot.contains("dark olive"));
+
+        let mut descendants = state.descendants("shiny gold");
+        descendants.insert("shiny gold");
+        let dot = state.to_dot(Some(&descendants), None);
+        assert!(dot.contains("\"shiny gold\" -> \"vibrant plum\" [label=\"2\"];"));
+        assert!(!dot.contains("light red"));
+    }
+
+    #[test]
+    fn hierarchy() {
+        let state = sample();
+        let stats = state.hierarchy_stats().unwrap();
+        assert_eq!(stats[0], ("dark orange", 4, 7, 406));
+        assert_eq!(stats[1], ("light red", 4, 7, 186));
+        assert!(stats.contains(&("shiny gold", 2, 4, 32)));
+        assert!(stats.contains(&("faded blue", 0, 0, 0)));
+        assert_eq!(state.leaves(), vec!["dotted black", "faded blue"]);
+    }
+}
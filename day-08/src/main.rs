@@ -1,115 +1,1448 @@
-use regex::Regex;
-use std::collections::HashSet;
-use std::io::{stdin, Read};
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{stdin, stdout, BufRead, Read, Write};
 
-#[derive(Debug)]
+// Steps a program may take before it is stopped, unless `--budget` says otherwise
+static DEFAULT_BUDGET: u64 = 10_000_000;
+
+// Steps `--trace` may record; the recording holds one entry per step
+static TRACE_BUDGET: u64 = 1_000_000;
+
+// Register names; the accumulator is register 0
+static REGISTERS: [&str; 5] = ["acc", "a", "b", "c", "d"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum Operator {
     Acc,
     Jmp,
     Nop,
+    Mul,
+    Add,
+    Set,
+    Mov,
+    Jz,
+    Jnz,
+}
+
+#[derive(Debug, PartialEq)]
+enum Operand {
+    Register,
+    Value,
+    Offset,
+}
+
+#[derive(Debug)]
+struct Opcode {
+    mnemonic: &'static str,
+    operator: Operator,
+    operands: &'static [Operand],
+}
+
+// The instruction set, with the operands each instruction takes in source
+// order. Instructions without a register operand work on the accumulator, and
+// `mov` keeps its source register in the argument.
+static INSTRUCTION_SET: [Opcode; 9] = [
+    Opcode {
+        mnemonic: "acc",
+        operator: Operator::Acc,
+        operands: &[Operand::Value],
+    },
+    Opcode {
+        mnemonic: "jmp",
+        operator: Operator::Jmp,
+        operands: &[Operand::Offset],
+    },
+    Opcode {
+        mnemonic: "nop",
+        operator: Operator::Nop,
+        operands: &[Operand::Value],
+    },
+    Opcode {
+        mnemonic: "mul",
+        operator: Operator::Mul,
+        operands: &[Operand::Register, Operand::Value],
+    },
+    Opcode {
+        mnemonic: "add",
+        operator: Operator::Add,
+        operands: &[Operand::Register, Operand::Value],
+    },
+    Opcode {
+        mnemonic: "set",
+        operator: Operator::Set,
+        operands: &[Operand::Register, Operand::Value],
+    },
+    Opcode {
+        mnemonic: "mov",
+        operator: Operator::Mov,
+        operands: &[Operand::Register, Operand::Register],
+    },
+    Opcode {
+        mnemonic: "jz",
+        operator: Operator::Jz,
+        operands: &[Operand::Register, Operand::Offset],
+    },
+    Opcode {
+        mnemonic: "jnz",
+        operator: Operator::Jnz,
+        operands: &[Operand::Register, Operand::Offset],
+    },
+];
+
+#[derive(Debug)]
+struct Instruction {
+    operator: Operator,
+    register: usize,
+    argument: i32,
 }
 
 #[derive(Debug)]
 struct State {
-    instructions: Vec<(Operator, i32)>,
+    instructions: Vec<Instruction>,
+    conditional: bool,
+    budget: u64,
+}
+
+// How a run of the program ended, with the final accumulator
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Terminated {
+        accumulator: i32,
+    },
+    Looped {
+        address: usize,
+        accumulator: i32,
+    },
+    Faulted {
+        address: usize,
+        target: i64,
+        accumulator: i32,
+    },
+    BudgetExhausted {
+        steps: u64,
+        accumulator: i32,
+    },
+}
+
+// Registers of the handheld console
+#[derive(Debug, Clone, PartialEq)]
+struct Cpu {
+    ip: usize,
+    registers: [i32; 5],
+}
+
+// Detects a repeated machine state with memory bounded by the program rather
+// than the run. Without conditional jumps the registers never affect control
+// flow, so a revisited address proves a loop and it is enough to keep the step
+// at which each address was first seen. Otherwise it uses Brent's algorithm,
+// comparing each state with a saved one whose distance keeps doubling.
+#[derive(Debug)]
+enum LoopDetector {
+    Addresses(Vec<Option<u64>>),
+    Brent { saved: Cpu, power: u64, length: u64 },
+}
+
+// Stops execution when the accumulator changes, or when it reaches a value
+#[derive(Debug)]
+enum Watchpoint {
+    Change,
+    Equals(i32),
+}
+
+// One executed instruction, with the accumulator after it ran
+#[derive(Debug)]
+struct TraceStep {
+    ip: usize,
+    accumulator: i32,
+}
+
+// The cycle of addresses a non-terminating program repeats forever. `entry` is
+// the first address of the cycle and `entered_from` the instruction that led
+// into it, if the program did not start inside the loop.
+#[derive(Debug)]
+struct Loop {
+    entry: usize,
+    entered_from: Option<usize>,
+    cycle: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct Trace {
+    steps: Vec<TraceStep>,
+    counts: Vec<u32>,
+    detected_loop: Option<Loop>,
+    outcome: Outcome,
+}
+
+// A single jmp/nop flip that makes the program terminate
+#[derive(Debug)]
+struct Repair {
+    index: usize,
+    original: Operator,
+    patched: Operator,
+}
+
+// A maximal run of instructions entered only at `start` and left only after
+// its last instruction. Successors are addresses, where the end of the program
+// is `instructions.len()`; `faults` marks a jump before address 0.
+#[derive(Debug)]
+struct Block {
+    start: usize,
+    end: usize,
+    successors: Vec<usize>,
+    faults: bool,
+}
+
+impl Operator {
+    fn is_jump(&self) -> bool {
+        matches!(self, Operator::Jmp | Operator::Jz | Operator::Jnz)
+    }
+
+    fn opcode(&self) -> &'static Opcode {
+        INSTRUCTION_SET
+            .iter()
+            .find(|x| x.operator == *self)
+            .unwrap()
+    }
+
+    fn mnemonic(&self) -> &'static str {
+        self.opcode().mnemonic
+    }
+}
+
+impl Outcome {
+    fn accumulator(&self) -> i32 {
+        match self {
+            Outcome::Terminated { accumulator }
+            | Outcome::Looped { accumulator, .. }
+            | Outcome::Faulted { accumulator, .. }
+            | Outcome::BudgetExhausted { accumulator, .. } => *accumulator,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Terminated { .. } => write!(f, "terminated"),
+            Outcome::Looped { address, .. } => write!(f, "looped at {}", address),
+            Outcome::Faulted {
+                address, target, ..
+            } => write!(f, "faulted at {} jumping to {}", address, target),
+            Outcome::BudgetExhausted { steps, .. } => {
+                write!(f, "exhausted the budget of {} steps", steps)
+            }
+        }
+    }
+}
+
+impl LoopDetector {
+    fn new(state: &State) -> LoopDetector {
+        if state.conditional {
+            LoopDetector::Brent {
+                saved: Cpu::new(),
+                power: 1,
+                length: 0,
+            }
+        } else {
+            LoopDetector::Addresses(vec![None; state.instructions.len()])
+        }
+    }
+
+    // Records the cpu before step `step` of the run executes, starting from
+    // step 0. Once a state repeats, returns the length of the cycle.
+    fn observe(&mut self, cpu: &Cpu, step: u64) -> Option<u64> {
+        match self {
+            LoopDetector::Addresses(first_steps) => match first_steps[cpu.ip] {
+                Some(first) => Some(step - first),
+                None => {
+                    first_steps[cpu.ip] = Some(step);
+                    None
+                }
+            },
+            LoopDetector::Brent {
+                saved,
+                power,
+                length,
+            } => {
+                if step == 0 {
+                    return None;
+                }
+                *length += 1;
+                if cpu == saved {
+                    return Some(*length);
+                }
+                if length == power {
+                    *saved = cpu.clone();
+                    *power *= 2;
+                    *length = 0;
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Cpu {
+    fn new() -> Cpu {
+        Cpu {
+            ip: 0,
+            registers: [0; 5],
+        }
+    }
+
+    fn accumulator(&self) -> i32 {
+        self.registers[0]
+    }
+}
+
+fn parse_value(text: &str) -> Result<i32, String> {
+    text.parse::<i32>()
+        .map_err(|_| format!("invalid value {}", text))
+}
+
+fn parse_register(text: &str) -> Result<usize, String> {
+    REGISTERS
+        .iter()
+        .position(|x| *x == text)
+        .ok_or(format!("unknown register {}", text))
+}
+
+fn is_label(text: &str) -> bool {
+    text.starts_with(|x: char| x.is_ascii_alphabetic() || x == '_')
+        && text.chars().all(|x| x.is_ascii_alphanumeric() || x == '_')
+}
+
+// Parses one instruction at `address`, resolving labels to relative offsets
+fn parse_instruction(
+    text: &str,
+    address: usize,
+    labels: &HashMap<&str, usize>,
+) -> Result<Instruction, String> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let opcode = INSTRUCTION_SET
+        .iter()
+        .find(|x| x.mnemonic == words[0])
+        .ok_or(format!("unknown instruction {}", words[0]))?;
+    if words.len() - 1 != opcode.operands.len() {
+        return Err(format!(
+            "{} takes {} operand(s), found {}",
+            opcode.mnemonic,
+            opcode.operands.len(),
+            words.len() - 1
+        ));
+    }
+
+    let mut instruction = Instruction {
+        operator: opcode.operator,
+        register: 0,
+        argument: 0,
+    };
+    for (index, (operand, word)) in opcode.operands.iter().zip(&words[1..]).enumerate() {
+        match operand {
+            Operand::Register if index == 0 => instruction.register = parse_register(word)?,
+            Operand::Register => instruction.argument = parse_register(word)? as i32,
+            Operand::Value => instruction.argument = parse_value(word)?,
+            Operand::Offset if is_label(word) => {
+                let target = labels.get(word).ok_or(format!("unknown label {}", word))?;
+                instruction.argument = *target as i32 - address as i32;
+            }
+            Operand::Offset => instruction.argument = parse_value(word)?,
+        }
+    }
+
+    Ok(instruction)
 }
 
 impl State {
-    fn new(lines: &[&str]) -> State {
+    // Assembles the program in two passes: the first assigns addresses to
+    // `label:` prefixes, the second parses the instructions. Blank lines and
+    // `#` comments are ignored; every other line must be a valid instruction.
+    fn new(lines: &[&str]) -> Result<State, String> {
+        let mut labels = HashMap::new();
+        let mut statements = Vec::new();
+        let mut errors = Vec::new();
+
+        for (index, line) in lines.iter().enumerate() {
+            let mut text = line.split('#').next().unwrap().trim();
+            if let Some((label, rest)) = text.split_once(':') {
+                let label = label.trim();
+                if !is_label(label) {
+                    errors.push((index + 1, format!("invalid label {}", label)));
+                } else if labels.insert(label, statements.len()).is_some() {
+                    errors.push((index + 1, format!("duplicate label {}", label)));
+                }
+                text = rest.trim();
+            }
+            if !text.is_empty() {
+                statements.push((index + 1, text));
+            }
+        }
+
         let mut instructions = Vec::new();
+        for (address, (line_number, text)) in statements.iter().enumerate() {
+            match parse_instruction(text, address, &labels) {
+                Ok(instruction) => instructions.push(instruction),
+                Err(e) => errors.push((*line_number, e)),
+            }
+        }
+
+        if !errors.is_empty() {
+            errors.sort();
+            let errors: Vec<String> = errors
+                .iter()
+                .map(|(line_number, e)| format!("line {}: {}", line_number, e))
+                .collect();
+            return Err(errors.join("\n"));
+        }
+
+        let conditional = instructions
+            .iter()
+            .any(|x| x.operator == Operator::Jz || x.operator == Operator::Jnz);
+
+        Ok(State {
+            instructions,
+            conditional,
+            budget: DEFAULT_BUDGET,
+        })
+    }
+
+    // The part of a machine state that must repeat for LoopDetector to see a loop
+    fn loop_key(&self, cpu: &Cpu) -> (usize, [i32; 5]) {
+        if self.conditional {
+            (cpu.ip, cpu.registers)
+        } else {
+            (cpu.ip, [0; 5])
+        }
+    }
+
+    // Replays the run with two cpus a cycle apart to find the first repeated
+    // state. Returns the step at which the cycle starts and the cpu one cycle
+    // later, just before it would repeat that step.
+    fn first_repeat(&self, length: u64) -> (u64, Cpu) {
+        let mut ahead = Cpu::new();
+        for _ in 0..length {
+            self.step(&mut ahead).unwrap();
+        }
+
+        let mut behind = Cpu::new();
+        let mut start = 0;
+        while self.loop_key(&behind) != self.loop_key(&ahead) {
+            self.step(&mut behind).unwrap();
+            self.step(&mut ahead).unwrap();
+            start += 1;
+        }
+
+        (start, ahead)
+    }
+
+    fn is_terminated(&self, cpu: &Cpu) -> bool {
+        cpu.ip >= self.instructions.len()
+    }
+
+    // Executes the instruction at the cpu's instruction pointer. Jumping before
+    // address 0 is a fault, which leaves the cpu unchanged and returns the
+    // target address; jumping past the end terminates the program.
+    fn step(&self, cpu: &mut Cpu) -> Result<(), i64> {
+        let instruction = &self.instructions[cpu.ip];
+        let register = instruction.register;
+        let argument = instruction.argument;
+        let mut target = cpu.ip as i64 + 1;
+
+        match instruction.operator {
+            Operator::Acc | Operator::Add => {
+                cpu.registers[register] = cpu.registers[register].wrapping_add(argument);
+            }
+            Operator::Mul => {
+                cpu.registers[register] = cpu.registers[register].wrapping_mul(argument);
+            }
+            Operator::Set => {
+                cpu.registers[register] = argument;
+            }
+            Operator::Mov => {
+                cpu.registers[register] = cpu.registers[argument as usize];
+            }
+            Operator::Jmp => target = cpu.ip as i64 + argument as i64,
+            Operator::Nop => {}
+            Operator::Jz | Operator::Jnz => {
+                if (cpu.registers[register] == 0) == (instruction.operator == Operator::Jz) {
+                    target = cpu.ip as i64 + argument as i64;
+                }
+            }
+        }
+
+        if target < 0 {
+            return Err(target);
+        }
+        cpu.ip = target as usize;
+
+        Ok(())
+    }
 
-        let rx = Regex::new(r"^(acc|jmp|nop) ((\+|-)\d+)$").unwrap();
-        for line in lines.iter() {
-            if let Some(cap) = rx.captures(line) {
-                let operator = match &cap[1] {
-                    "acc" => Operator::Acc,
-                    "jmp" => Operator::Jmp,
-                    "nop" => Operator::Nop,
-                    _ => panic!("unknown operator {}", &cap[1]),
+    // The operands of an instruction, formatted as they are written in source
+    fn operands(&self, index: usize) -> String {
+        let instruction = &self.instructions[index];
+        let operands: Vec<String> = instruction
+            .operator
+            .opcode()
+            .operands
+            .iter()
+            .enumerate()
+            .map(|(position, operand)| match operand {
+                Operand::Register if position == 0 => REGISTERS[instruction.register].to_string(),
+                Operand::Register => REGISTERS[instruction.argument as usize].to_string(),
+                Operand::Value | Operand::Offset => format!("{:+}", instruction.argument),
+            })
+            .collect();
+
+        operands.join(" ")
+    }
+
+    fn disassemble(&self, index: usize) -> String {
+        format!(
+            "{} {}",
+            self.instructions[index].operator.mnemonic(),
+            self.operands(index)
+        )
+    }
+
+    // The whole program in canonical form, with labels resolved to offsets
+    fn disassemble_all(&self) -> String {
+        (0..self.instructions.len())
+            .map(|x| self.disassemble(x) + "\n")
+            .collect()
+    }
+
+    // Runs the program like `execute`, recording every step and, if the
+    // program does not terminate, the loop it is stuck in. Tracing stops after
+    // TRACE_BUDGET steps even when the step budget allows more.
+    fn trace(&self) -> Trace {
+        let budget = self.budget.min(TRACE_BUDGET);
+        let mut cpu = Cpu::new();
+        let mut steps: Vec<TraceStep> = Vec::new();
+        let mut detector = LoopDetector::new(self);
+        let mut detected_loop = None;
+
+        let outcome = loop {
+            let accumulator = cpu.accumulator();
+            if self.is_terminated(&cpu) {
+                break Outcome::Terminated { accumulator };
+            }
+            if let Some(length) = detector.observe(&cpu, steps.len() as u64) {
+                let (start, repeated) = self.first_repeat(length);
+                let start = start as usize;
+                steps.truncate(start + length as usize);
+                detected_loop = Some(Loop {
+                    entry: repeated.ip,
+                    entered_from: start.checked_sub(1).map(|x| steps[x].ip),
+                    cycle: steps[start..].iter().map(|x| x.ip).collect(),
+                });
+                break Outcome::Looped {
+                    address: repeated.ip,
+                    accumulator: repeated.accumulator(),
+                };
+            }
+            if steps.len() as u64 >= budget {
+                break Outcome::BudgetExhausted {
+                    steps: budget,
+                    accumulator,
                 };
-                let argument: i32 = cap[2].parse::<i32>().unwrap();
-                instructions.push((operator, argument));
             }
+
+            let ip = cpu.ip;
+            if let Err(target) = self.step(&mut cpu) {
+                break Outcome::Faulted {
+                    address: ip,
+                    target,
+                    accumulator,
+                };
+            }
+            steps.push(TraceStep {
+                ip,
+                accumulator: cpu.accumulator(),
+            });
+        };
+
+        let mut counts = vec![0; self.instructions.len()];
+        for step in steps.iter() {
+            counts[step.ip] += 1;
         }
 
-        State { instructions }
+        Trace {
+            steps,
+            counts,
+            detected_loop,
+            outcome,
+        }
     }
 
-    fn execute(&self) -> (bool, i32) {
-        let mut accumulator = 0;
-        let mut ip = 0;
-        let mut visited = HashSet::new();
+    fn print_trace(&self, trace: &Trace) {
+        for (index, step) in trace.steps.iter().enumerate() {
+            println!(
+                "{:>6}  {:>5}  {:<10} acc {}",
+                index,
+                step.ip,
+                self.disassemble(step.ip),
+                step.accumulator
+            );
+        }
+
+        let accumulator = trace.outcome.accumulator();
+        match &trace.detected_loop {
+            Some(detected_loop) => {
+                let cycle: Vec<String> =
+                    detected_loop.cycle.iter().map(|x| x.to_string()).collect();
+                match detected_loop.entered_from {
+                    Some(from) => println!(
+                        "Loop entered at {} from {}, accumulator {}",
+                        detected_loop.entry, from, accumulator
+                    ),
+                    None => println!(
+                        "Loop starts at {}, accumulator {}",
+                        detected_loop.entry, accumulator
+                    ),
+                }
+                println!(
+                    "Cycle ({} instructions): {}",
+                    cycle.len(),
+                    cycle.join(" -> ")
+                );
+            }
+            None => println!("Program {}, accumulator {}", trace.outcome, accumulator),
+        }
+
+        println!("Execution counts:");
+        for (index, count) in trace.counts.iter().enumerate() {
+            println!("{:>5}  {:<10} {}", index, self.disassemble(index), count);
+        }
+    }
+
+    fn trace_csv(&self, trace: &Trace) -> String {
+        let mut csv = String::from("step,ip,op,arg,acc\n");
+        for (index, step) in trace.steps.iter().enumerate() {
+            csv.push_str(&format!(
+                "{},{},{},{},{}\n",
+                index,
+                step.ip,
+                self.instructions[step.ip].operator.mnemonic(),
+                self.operands(step.ip),
+                step.accumulator
+            ));
+        }
+
+        csv
+    }
+
+    fn execute(&self) -> Outcome {
+        let mut cpu = Cpu::new();
+        let mut detector = LoopDetector::new(self);
+        let mut steps = 0;
 
         loop {
-            if ip >= self.instructions.len() {
-                return (true, accumulator);
+            let accumulator = cpu.accumulator();
+            if self.is_terminated(&cpu) {
+                return Outcome::Terminated { accumulator };
             }
-            if visited.contains(&ip) {
-                break;
+            if let Some(length) = detector.observe(&cpu, steps) {
+                let (_start, repeated) = self.first_repeat(length);
+                return Outcome::Looped {
+                    address: repeated.ip,
+                    accumulator: repeated.accumulator(),
+                };
+            }
+            if steps >= self.budget {
+                return Outcome::BudgetExhausted { steps, accumulator };
+            }
+
+            let address = cpu.ip;
+            if let Err(target) = self.step(&mut cpu) {
+                return Outcome::Faulted {
+                    address,
+                    target,
+                    accumulator,
+                };
+            }
+            steps += 1;
+        }
+    }
+
+    fn run_p1(&self) -> Outcome {
+        self.execute()
+    }
+
+    // The addresses that may execute after `index` if it held `operator`; the
+    // end of the program, and any jump past it, is address `instructions.len()`
+    fn successors(&self, index: usize, operator: Operator) -> Vec<usize> {
+        let next = index as i64 + 1;
+        let target = index as i64 + self.instructions[index].argument as i64;
+        let targets = match operator {
+            Operator::Jmp => vec![target],
+            Operator::Jz | Operator::Jnz => vec![next, target],
+            _ => vec![next],
+        };
+
+        targets
+            .into_iter()
+            .filter(|x| *x >= 0)
+            .map(|x| (x as usize).min(self.instructions.len()))
+            .collect()
+    }
+
+    // Marks the addresses from which the unmodified program may terminate, by
+    // walking the control flow backwards from the end of the program
+    fn terminating(&self) -> Vec<bool> {
+        let end = self.instructions.len();
+        let mut predecessors = vec![Vec::new(); end + 1];
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            for target in self.successors(index, instruction.operator) {
+                predecessors[target].push(index);
             }
-            visited.insert(ip);
+        }
 
-            match self.instructions[ip].0 {
-                Operator::Acc => {
-                    accumulator += self.instructions[ip].1;
-                    ip += 1;
+        let mut terminating = vec![false; end + 1];
+        terminating[end] = true;
+        let mut pending = vec![end];
+        while let Some(address) = pending.pop() {
+            for &predecessor in &predecessors[address] {
+                if !terminating[predecessor] {
+                    terminating[predecessor] = true;
+                    pending.push(predecessor);
                 }
-                Operator::Jmp => {
-                    let offset = self.instructions[ip].1;
-                    ip = (ip as i32 + offset) as usize;
+            }
+        }
+
+        terminating
+    }
+
+    // Lists, in execution order, the instructions on the original path whose
+    // flip leads into the terminating set, in time linear in the program length
+    fn repair_candidates(&self) -> Vec<Repair> {
+        let terminating = self.terminating();
+        let mut detector = LoopDetector::new(self);
+        let mut considered = vec![false; self.instructions.len()];
+        let mut cpu = Cpu::new();
+        let mut steps = 0;
+        let mut candidates = Vec::new();
+
+        while !self.is_terminated(&cpu)
+            && steps < self.budget
+            && detector.observe(&cpu, steps).is_none()
+        {
+            if !considered[cpu.ip] {
+                considered[cpu.ip] = true;
+
+                let original = self.instructions[cpu.ip].operator;
+                let patched = match original {
+                    Operator::Jmp => Some(Operator::Nop),
+                    Operator::Nop => Some(Operator::Jmp),
+                    _ => None,
+                };
+                if let Some(patched) = patched {
+                    if self
+                        .successors(cpu.ip, patched)
+                        .iter()
+                        .any(|x| terminating[*x])
+                    {
+                        candidates.push(Repair {
+                            index: cpu.ip,
+                            original,
+                            patched,
+                        });
+                    }
                 }
-                Operator::Nop => {
-                    ip += 1;
+            }
+
+            if self.step(&mut cpu).is_err() {
+                break;
+            }
+            steps += 1;
+        }
+
+        candidates
+    }
+
+    // Marks the addresses that some path from address 0 may execute
+    fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.instructions.len() + 1];
+        let mut pending = vec![0];
+        while let Some(address) = pending.pop() {
+            if reachable[address] {
+                continue;
+            }
+            reachable[address] = true;
+            if address < self.instructions.len() {
+                pending.extend(self.successors(address, self.instructions[address].operator));
+            }
+        }
+
+        reachable
+    }
+
+    // Splits the program into basic blocks. A block starts at address 0, at
+    // every jump target and after every jump.
+    fn basic_blocks(&self) -> Vec<Block> {
+        let end = self.instructions.len();
+        let mut leaders = vec![false; end + 1];
+        leaders[0] = true;
+        leaders[end] = true;
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            if instruction.operator.is_jump() {
+                for target in self.successors(index, instruction.operator) {
+                    leaders[target] = true;
                 }
+                leaders[index + 1] = true;
             }
         }
 
-        (false, accumulator)
+        let mut blocks = Vec::new();
+        let mut start = 0;
+        for (address, _) in leaders.iter().enumerate().skip(1).filter(|(_, x)| **x) {
+            let last = address - 1;
+            let operator = self.instructions[last].operator;
+            let successors = self.successors(last, operator);
+            let targets = match operator {
+                Operator::Jz | Operator::Jnz => 2,
+                _ => 1,
+            };
+            blocks.push(Block {
+                start,
+                end: address,
+                faults: successors.len() < targets,
+                successors,
+            });
+            start = address;
+        }
+
+        blocks
     }
 
-    fn run_p1(&self) -> i32 {
-        let (_terminated, accumulator) = self.execute();
+    // Renders the control-flow graph. Unreachable blocks are dashed and grey,
+    // reachable blocks that can never terminate are filled red.
+    fn to_dot(&self) -> String {
+        let end = self.instructions.len();
+        let blocks = self.basic_blocks();
+        let reachable = self.reachable();
+        let terminating = self.terminating();
 
-        accumulator
+        let mut output =
+            String::from("digraph program {\n    node [shape=box, fontname=monospace];\n");
+        output.push_str("    start [shape=circle];\n    exit [shape=doublecircle];\n");
+        if blocks.iter().any(|x| x.faults) {
+            output.push_str("    fault [shape=octagon, style=filled, fillcolor=orange];\n");
+        }
+
+        for block in blocks.iter() {
+            let label: String = (block.start..block.end)
+                .map(|x| format!("{}: {}\\l", x, self.disassemble(x)))
+                .collect();
+            let style = if !reachable[block.start] {
+                ", style=dashed, color=gray, fontcolor=gray"
+            } else if !terminating[block.start] {
+                ", style=filled, fillcolor=lightcoral"
+            } else {
+                ""
+            };
+            output.push_str(&format!(
+                "    b{} [label=\"{}\"{}];\n",
+                block.start, label, style
+            ));
+        }
+
+        if end > 0 {
+            output.push_str("    start -> b0;\n");
+        } else {
+            output.push_str("    start -> exit;\n");
+        }
+        for block in blocks.iter() {
+            let last = block.end - 1;
+            let conditional = matches!(
+                self.instructions[last].operator,
+                Operator::Jz | Operator::Jnz
+            );
+            for &target in block.successors.iter() {
+                let node = if target == end {
+                    "exit".to_string()
+                } else {
+                    format!("b{}", target)
+                };
+                let label = if conditional && target != block.end {
+                    " [label=\"taken\"]"
+                } else {
+                    ""
+                };
+                output.push_str(&format!("    b{} -> {}{};\n", block.start, node, label));
+            }
+            if block.faults {
+                output.push_str(&format!("    b{} -> fault;\n", block.start));
+            }
+        }
+        output.push_str("}\n");
+
+        output
     }
 
-    fn run_p2(&mut self) -> i32 {
-        for index in 0..self.instructions.len() {
-            match self.instructions[index].0 {
-                Operator::Acc => {}
-                Operator::Jmp => {
-                    let argument = self.instructions[index].1;
-                    self.instructions[index] = (Operator::Nop, argument);
-                    let (terminated, accumulator) = self.execute();
-                    if terminated {
-                        return accumulator;
+    // Prints the block count and the unreachable and never-terminating
+    // addresses, with consecutive addresses collapsed into ranges
+    fn print_analysis(&self) {
+        let reachable = self.reachable();
+        let terminating = self.terminating();
+        let ranges = |marked: &dyn Fn(usize) -> bool| -> String {
+            let mut ranges: Vec<String> = Vec::new();
+            let mut first = None;
+            for address in 0..=self.instructions.len() {
+                let inside = address < self.instructions.len() && marked(address);
+                match (inside, first) {
+                    (true, None) => first = Some(address),
+                    (false, Some(start)) if start + 1 == address => {
+                        ranges.push(start.to_string());
+                        first = None;
                     }
-                    self.instructions[index] = (Operator::Jmp, argument);
+                    (false, Some(start)) => {
+                        ranges.push(format!("{}-{}", start, address - 1));
+                        first = None;
+                    }
+                    _ => {}
                 }
-                Operator::Nop => {
-                    let argument = self.instructions[index].1;
-                    self.instructions[index] = (Operator::Jmp, argument);
-                    let (terminated, accumulator) = self.execute();
-                    if terminated {
-                        return accumulator;
+            }
+            if ranges.is_empty() {
+                "none".to_string()
+            } else {
+                ranges.join(", ")
+            }
+        };
+
+        println!("Basic blocks: {}", self.basic_blocks().len());
+        println!("Unreachable: {}", ranges(&|x| !reachable[x]));
+        println!("Never terminate: {}", ranges(&|x| !terminating[x]));
+    }
+
+    fn run_p2(&mut self) -> Option<(Repair, i32)> {
+        for repair in self.repair_candidates() {
+            self.instructions[repair.index].operator = repair.patched;
+            let outcome = self.execute();
+            self.instructions[repair.index].operator = repair.original;
+
+            if let Outcome::Terminated { accumulator } = outcome {
+                return Some((repair, accumulator));
+            }
+        }
+
+        None
+    }
+}
+
+struct Debugger<'a> {
+    state: &'a State,
+    cpu: Cpu,
+    breakpoints: BTreeSet<usize>,
+    watchpoint: Option<Watchpoint>,
+    detector: LoopDetector,
+    steps: u64,
+}
+
+impl<'a> Debugger<'a> {
+    fn new(state: &'a State) -> Debugger<'a> {
+        Debugger {
+            state,
+            cpu: Cpu::new(),
+            breakpoints: BTreeSet::new(),
+            watchpoint: None,
+            detector: LoopDetector::new(state),
+            steps: 0,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.cpu = Cpu::new();
+        self.detector = LoopDetector::new(self.state);
+        self.steps = 0;
+    }
+
+    fn show_registers(&self) {
+        let registers: Vec<String> = REGISTERS
+            .iter()
+            .zip(self.cpu.registers.iter())
+            .map(|(name, value)| format!("{} {}", name, value))
+            .collect();
+        println!("ip {}  {}", self.cpu.ip, registers.join("  "));
+    }
+
+    // Lists the instructions around the address, marking ip and breakpoints
+    fn show_listing(&self, center: usize) {
+        let first = center.saturating_sub(4);
+        let last = (center + 5).min(self.state.instructions.len());
+
+        for index in first..last {
+            let marker = if index == self.cpu.ip { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&index) {
+                '*'
+            } else {
+                ' '
+            };
+            println!(
+                "{} {}{:>5}  {}",
+                marker,
+                breakpoint,
+                index,
+                self.state.disassemble(index)
+            );
+        }
+    }
+
+    fn show_position(&self) {
+        if self.state.is_terminated(&self.cpu) {
+            println!(
+                "Program terminated at ip {} with accumulator {}",
+                self.cpu.ip,
+                self.cpu.accumulator()
+            );
+        } else {
+            println!(
+                "{:>5}  {}",
+                self.cpu.ip,
+                self.state.disassemble(self.cpu.ip)
+            );
+        }
+    }
+
+    // Executes one instruction; returns the reason execution should stop
+    fn single_step(&mut self) -> Option<String> {
+        if self.state.is_terminated(&self.cpu) {
+            return Some("program has terminated".to_string());
+        }
+
+        let before = self.cpu.accumulator();
+        if self.steps == 0 {
+            self.detector.observe(&self.cpu, 0);
+        }
+        if let Err(target) = self.state.step(&mut self.cpu) {
+            return Some(format!("fault: jump from {} to {}", self.cpu.ip, target));
+        }
+        self.steps += 1;
+
+        if self.state.is_terminated(&self.cpu) {
+            return Some("program terminated".to_string());
+        }
+        let repeated = self.detector.observe(&self.cpu, self.steps);
+        match self.watchpoint {
+            Some(Watchpoint::Change) if self.cpu.accumulator() != before => {
+                return Some(format!(
+                    "watchpoint: accumulator changed from {} to {}",
+                    before,
+                    self.cpu.accumulator()
+                ));
+            }
+            Some(Watchpoint::Equals(value))
+                if self.cpu.accumulator() == value && before != value =>
+            {
+                return Some(format!("watchpoint: accumulator reached {}", value));
+            }
+            _ => {}
+        }
+        if self.breakpoints.contains(&self.cpu.ip) {
+            return Some(format!("breakpoint at {}", self.cpu.ip));
+        }
+        if let Some(length) = repeated {
+            return Some(format!(
+                "loop of {} steps, instruction {} is about to repeat",
+                length, self.cpu.ip
+            ));
+        }
+
+        None
+    }
+
+    fn run_command(&mut self, command: &str, arguments: &[&str]) -> Result<bool, String> {
+        let address = |text: Option<&&str>| -> Result<usize, String> {
+            let text = text.ok_or("an address is required")?;
+            let address = text
+                .parse::<usize>()
+                .map_err(|_| format!("invalid address {}", text))?;
+            if address >= self.state.instructions.len() {
+                return Err(format!("address {} is outside the program", address));
+            }
+            Ok(address)
+        };
+
+        match command {
+            "s" | "step" => {
+                let count = match arguments.first() {
+                    Some(text) => text
+                        .parse::<usize>()
+                        .map_err(|_| format!("invalid count {}", text))?,
+                    None => 1,
+                };
+                for _ in 0..count {
+                    if let Some(reason) = self.single_step() {
+                        println!("Stopped: {}", reason);
+                        break;
                     }
-                    self.instructions[index] = (Operator::Nop, argument);
                 }
+                self.show_position();
             }
+            "c" | "continue" => {
+                let mut steps = 0;
+                let reason = loop {
+                    if steps >= self.state.budget {
+                        break format!("step budget of {} exhausted", self.state.budget);
+                    }
+                    if let Some(reason) = self.single_step() {
+                        break reason;
+                    }
+                    steps += 1;
+                };
+                println!("Stopped: {}", reason);
+                self.show_position();
+            }
+            "b" | "break" => {
+                let address = address(arguments.first())?;
+                self.breakpoints.insert(address);
+                println!("Breakpoint set at {}", address);
+            }
+            "d" | "delete" => {
+                let address = address(arguments.first())?;
+                if self.breakpoints.remove(&address) {
+                    println!("Breakpoint at {} deleted", address);
+                } else {
+                    println!("No breakpoint at {}", address);
+                }
+            }
+            "w" | "watch" => {
+                self.watchpoint = match arguments.first() {
+                    Some(&"off") => None,
+                    Some(text) => Some(Watchpoint::Equals(
+                        text.parse::<i32>()
+                            .map_err(|_| format!("invalid value {}", text))?,
+                    )),
+                    None => Some(Watchpoint::Change),
+                };
+                match self.watchpoint {
+                    Some(Watchpoint::Change) => {
+                        println!("Watching for any change to the accumulator")
+                    }
+                    Some(Watchpoint::Equals(value)) => {
+                        println!("Watching for the accumulator to reach {}", value)
+                    }
+                    None => println!("Watchpoint cleared"),
+                }
+            }
+            "r" | "regs" => self.show_registers(),
+            "l" | "list" => {
+                let center = match arguments.first() {
+                    Some(_) => address(arguments.first())?,
+                    None => self.cpu.ip,
+                };
+                self.show_listing(center);
+            }
+            "reset" => {
+                self.restart();
+                self.show_position();
+            }
+            "h" | "help" => {
+                println!("step [N]       execute N instructions (default 1)");
+                println!("continue       run until a breakpoint, watchpoint, loop or termination");
+                println!("break ADDR     set a breakpoint");
+                println!("delete ADDR    delete a breakpoint");
+                println!("watch [VALUE]  stop when the accumulator changes or reaches VALUE");
+                println!("watch off      clear the watchpoint");
+                println!("regs           show ip and registers");
+                println!("list [ADDR]    disassemble around ip or ADDR");
+                println!("reset          restart the program");
+                println!("quit           leave the debugger");
+            }
+            "q" | "quit" => return Ok(false),
+            _ => return Err(format!("unknown command {} (try help)", command)),
         }
 
-        0
+        Ok(true)
+    }
+
+    fn repl(&mut self) {
+        self.show_position();
+
+        let input = stdin();
+        let mut lines = input.lock().lines();
+        loop {
+            print!("(dbg) ");
+            stdout().flush().unwrap();
+
+            let line = match lines.next() {
+                Some(line) => line.unwrap(),
+                None => break,
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+
+            match self.run_command(words[0], &words[1..]) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => println!("Error: {}", e),
+            }
+        }
     }
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut debug_path = None;
+    let mut trace = false;
+    let mut csv_path = None;
+    let mut disassemble = false;
+    let mut budget = None;
+    let mut cfg_path = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .unwrap_or_else(|| panic!("{} requires a value", arg))
+                .as_str()
+        };
+        match arg.as_str() {
+            "--debug" => debug_path = Some(value()),
+            "--trace" => trace = true,
+            "--csv" => csv_path = Some(value()),
+            "--disassemble" => disassemble = true,
+            "--budget" => budget = Some(value().parse::<u64>().unwrap()),
+            "--cfg" => cfg_path = Some(value()),
+            _ => panic!("unknown argument {}", arg),
+        }
+    }
+
+    if let Some(path) = debug_path {
+        let program = fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = program.lines().collect();
+        let mut state = State::new(&lines).unwrap_or_else(|e| panic!("{}", e));
+        state.budget = budget.unwrap_or(state.budget);
+        Debugger::new(&state).repl();
+        return;
+    }
+
     let mut input = String::new();
     stdin().read_to_string(&mut input).unwrap();
 
     let lines: Vec<&str> = input.lines().collect();
 
-    let mut state = State::new(&lines);
+    let mut state = State::new(&lines).unwrap_or_else(|e| panic!("{}", e));
+    state.budget = budget.unwrap_or(state.budget);
+
+    if let Some(path) = cfg_path {
+        fs::write(path, state.to_dot()).unwrap();
+        state.print_analysis();
+    }
+
+    if disassemble {
+        print!("{}", state.disassemble_all());
+        return;
+    }
+
+    if trace || csv_path.is_some() {
+        let result = state.trace();
+        if trace {
+            state.print_trace(&result);
+        }
+        if let Some(path) = csv_path {
+            fs::write(path, state.trace_csv(&result)).unwrap();
+        }
+        return;
+    }
+
+    let outcome = state.run_p1();
+    println!(
+        "Part 1: The accumulator contains {} ({})",
+        outcome.accumulator(),
+        outcome
+    );
+    if let Outcome::Terminated { .. } = outcome {
+        println!("Part 2: The program already terminates, no repair is needed");
+        return;
+    }
+    match state.run_p2() {
+        Some((repair, accumulator)) => {
+            println!("Part 2: The accumulator contains {}", accumulator);
+            println!(
+                "Patched instruction {}: {} -> {}",
+                repair.index,
+                state.disassemble(repair.index),
+                repair.patched.mnemonic()
+            );
+        }
+        None => println!("Part 2: No single jmp/nop flip terminates the program"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn loop_report() {
+        use crate::State;
+
+        let lines = [
+            "nop +0", "acc +1", "jmp +4", "acc +3", "jmp -3", "acc -99", "acc +1", "jmp -4",
+            "acc +6",
+        ];
+        let trace = State::new(&lines).unwrap().trace();
+        let detected_loop = trace.detected_loop.unwrap();
+
+        assert_eq!(trace.outcome.accumulator(), 5);
+        assert_eq!(detected_loop.entry, 1);
+        assert_eq!(detected_loop.entered_from, Some(0));
+        assert_eq!(detected_loop.cycle, vec![1, 2, 6, 7, 3, 4]);
+        assert_eq!(trace.counts[5], 0);
+    }
+
+    #[test]
+    fn linear_repair() {
+        use crate::{Operator, State};
+
+        let lines = [
+            "nop +0", "acc +1", "jmp +4", "acc +3", "jmp -3", "acc -99", "acc +1", "jmp -4",
+            "acc +6",
+        ];
+        let mut state = State::new(&lines).unwrap();
+        let (repair, accumulator) = state.run_p2().unwrap();
+
+        assert_eq!(repair.index, 7);
+        assert_eq!(repair.patched, Operator::Nop);
+        assert_eq!(accumulator, 8);
+    }
+
+    #[test]
+    fn assembler() {
+        use crate::{Outcome, State};
+
+        let lines = [
+            "# doubles acc five times",
+            "        set acc +1",
+            "        set b +5",
+            "top:    mul acc +2",
+            "        add b -1",
+            "        jnz b top",
+        ];
+        let state = State::new(&lines).unwrap();
+
+        assert_eq!(state.disassemble(4), "jnz b -2");
+        assert_eq!(state.execute(), Outcome::Terminated { accumulator: 32 });
+        assert_eq!(
+            State::new(&["acc +1", "jmp nowhere", "mul +2"]).unwrap_err(),
+            "line 2: unknown label nowhere\nline 3: mul takes 2 operand(s), found 1"
+        );
+    }
+
+    #[test]
+    fn outcomes() {
+        use crate::{Outcome, State};
+
+        let faulty = State::new(&["acc +1", "jmp -2"]).unwrap();
+        assert_eq!(
+            faulty.execute(),
+            Outcome::Faulted {
+                address: 1,
+                target: -1,
+                accumulator: 1
+            }
+        );
 
-    println!("Part 1: The accumulator contains {}", state.run_p1());
-    println!("Part 2: The accumulator contains {}", state.run_p2());
+        let past_end = State::new(&["acc +1", "jmp +5"]).unwrap();
+        assert_eq!(past_end.execute(), Outcome::Terminated { accumulator: 1 });
+
+        let mut counter = State::new(&["add a +1", "jnz a -1"]).unwrap();
+        counter.budget = 100;
+        assert_eq!(
+            counter.execute(),
+            Outcome::BudgetExhausted {
+                steps: 100,
+                accumulator: 0
+            }
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        use crate::State;
+
+        let lines = [
+            "nop +0", "acc +1", "jmp +4", "acc +3", "jmp -3", "acc -99", "acc +1", "jmp -4",
+            "acc +6",
+        ];
+        let state = State::new(&lines).unwrap();
+        let starts: Vec<usize> = state.basic_blocks().iter().map(|x| x.start).collect();
+        let reachable = state.reachable();
+        let terminating = state.terminating();
+
+        assert_eq!(starts, vec![0, 1, 3, 5, 6, 8]);
+        assert!(!reachable[5] && !reachable[8] && reachable[7]);
+        assert!(terminating[8] && !terminating[5]);
+    }
+
+    #[test]
+    fn long_conditional_loop() {
+        use crate::{Outcome, State};
+
+        let countdown = ["set b +2000000", "add b -1", "jnz b -1", "acc +7"];
+        assert_eq!(
+            State::new(&countdown).unwrap().execute(),
+            Outcome::Terminated { accumulator: 7 }
+        );
+
+        let stuck = State::new(&["set b +2000000", "add b -1", "jnz b -1", "jmp +0"]).unwrap();
+        assert_eq!(
+            stuck.execute(),
+            Outcome::Looped {
+                address: 3,
+                accumulator: 0
+            }
+        );
+
+        let flipping = State::new(&["set a +1", "nop +0", "mul a -1", "jnz a -2"]).unwrap();
+        let trace = flipping.trace();
+        let detected_loop = trace.detected_loop.unwrap();
+        assert_eq!(trace.outcome.accumulator(), 0);
+        assert_eq!(detected_loop.entered_from, Some(0));
+        assert_eq!(detected_loop.cycle, vec![1, 2, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn debugger() {
+        use crate::{Debugger, State};
+
+        let lines = [
+            "nop +0", "acc +1", "jmp +4", "acc +3", "jmp -3", "acc -99", "acc +1", "jmp -4",
+            "acc +6",
+        ];
+        let state = State::new(&lines).unwrap();
+        let mut debugger = Debugger::new(&state);
+
+        debugger.run_command("break", &["7"]).unwrap();
+        debugger.run_command("continue", &[]).unwrap();
+        assert_eq!(debugger.cpu.ip, 7);
+        assert!(debugger.run_command("break", &["9"]).is_err());
+
+        debugger.run_command("reset", &[]).unwrap();
+        assert_eq!((debugger.cpu.ip, debugger.cpu.accumulator()), (0, 0));
+
+        debugger.run_command("delete", &["7"]).unwrap();
+        debugger.run_command("watch", &["2"]).unwrap();
+        assert_eq!(debugger.single_step(), None);
+        assert_eq!(debugger.single_step(), None);
+        assert_eq!(debugger.single_step(), None);
+        assert_eq!(
+            debugger.single_step(),
+            Some("watchpoint: accumulator reached 2".to_string())
+        );
+        assert_eq!(debugger.single_step(), None);
+
+        let faulty = State::new(&["acc +1", "jmp -2"]).unwrap();
+        let mut debugger = Debugger::new(&faulty);
+        assert_eq!(debugger.single_step(), None);
+        assert_eq!(
+            debugger.single_step(),
+            Some("fault: jump from 1 to -1".to_string())
+        );
+        assert_eq!(debugger.cpu.ip, 1);
+    }
 }
@@ -1,41 +1,77 @@
 use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::env;
 use std::io::{stdin, Read};
 
-static PREAMBLE_LENGTH: usize = 25;
+static DEFAULT_PREAMBLE_LENGTH: usize = 25;
 
-fn find_invalid(numbers: &[u64]) -> Option<usize> {
-    let mut candidate = PREAMBLE_LENGTH;
+// The last `length` numbers of a stream, with a count of every sum of two of
+// them at different positions. Pushing a number updates the sums in O(length),
+// so checking the next number is a single lookup.
+struct Window {
+    length: usize,
+    values: VecDeque<u64>,
+    sums: HashMap<u64, u32>,
+}
 
-    while candidate < numbers.len() {
-        if !is_valid(candidate, numbers) {
-            return Some(candidate);
+impl Window {
+    fn new(length: usize) -> Window {
+        Window {
+            length,
+            values: VecDeque::with_capacity(length),
+            sums: HashMap::new(),
         }
+    }
 
-        candidate += 1;
+    fn is_full(&self) -> bool {
+        self.values.len() >= self.length
     }
 
-    None
+    fn allows(&self, value: u64) -> bool {
+        self.sums.contains_key(&value)
+    }
+
+    // Adds a number, evicting the oldest one once the window is full
+    fn push(&mut self, value: u64) {
+        if self.length == 0 {
+            return;
+        }
+        if self.is_full() {
+            let oldest = self.values.pop_front().unwrap();
+            for other in self.values.iter() {
+                let count = self.sums.get_mut(&(oldest + other)).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    self.sums.remove(&(oldest + other));
+                }
+            }
+        }
+
+        for other in self.values.iter() {
+            *self.sums.entry(value + other).or_insert(0) += 1;
+        }
+        self.values.push_back(value);
+    }
 }
 
-fn is_valid(target: usize, numbers: &[u64]) -> bool {
-    let mut base = target - PREAMBLE_LENGTH;
-    let mut other = base + 1;
+fn find_invalid(numbers: &[u64], preamble_length: usize) -> Option<usize> {
+    let mut window = Window::new(preamble_length);
 
-    while base < target - 1 {
-        while other < target {
-            if numbers[base] + numbers[other] == numbers[target] {
-                return true;
-            }
-            other += 1;
+    for (index, &number) in numbers.iter().enumerate() {
+        if index >= preamble_length && !window.allows(number) {
+            return Some(index);
         }
-        base += 1;
-        other = base + 1;
+        window.push(number);
     }
 
-    false
+    None
 }
 
 fn find_weakness(invalid: usize, numbers: &[u64]) -> Option<u64> {
+    if invalid < 2 {
+        return None;
+    }
+
     let mut base = 0;
     let mut other = base + 1;
 
@@ -63,12 +99,32 @@ fn find_weakness(invalid: usize, numbers: &[u64]) -> Option<u64> {
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut preamble_length = DEFAULT_PREAMBLE_LENGTH;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--preamble" => {
+                preamble_length = iter
+                    .next()
+                    .unwrap_or_else(|| panic!("{} requires a value", arg))
+                    .parse::<usize>()
+                    .unwrap();
+                if preamble_length < 2 {
+                    panic!("--preamble must be at least 2");
+                }
+            }
+            _ => panic!("unknown argument {}", arg),
+        }
+    }
+
     let mut input = String::new();
     stdin().read_to_string(&mut input).unwrap();
 
     let numbers: Vec<u64> = input.lines().map(|x| x.parse::<u64>().unwrap()).collect();
 
-    if let Some(invalid) = find_invalid(&numbers) {
+    if let Some(invalid) = find_invalid(&numbers, preamble_length) {
         println!("Part 1: the first invalid number is {}", numbers[invalid]);
 
         if let Some(weakness) = find_weakness(invalid, &numbers) {
@@ -76,3 +132,21 @@ fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn example() {
+        use crate::{find_invalid, find_weakness};
+
+        let numbers = [
+            35, 20, 15, 25, 47, 40, 62, 55, 65, 95, 102, 117, 150, 182, 127, 219, 299, 277, 309,
+            576,
+        ];
+        let invalid = find_invalid(&numbers, 5).unwrap();
+
+        assert_eq!(numbers[invalid], 127);
+        assert_eq!(find_weakness(invalid, &numbers), Some(62));
+        assert_eq!(find_weakness(0, &numbers), None);
+    }
+}